@@ -0,0 +1,50 @@
+use argh::FromArgs;
+use log::{error, info, LevelFilter};
+
+use prospect_backend::database::{open_store, schema_version};
+
+#[derive(FromArgs)]
+/// Apply pending schema migrations of Prospect database.
+struct MigrateOptions {
+  /// sql user
+  #[argh(option, short = 'u', default = "String::new()")]
+  sql_user: String,
+
+  /// sql ip
+  #[argh(option, short = 'a', default = "String::new()")]
+  sql_addr: String,
+
+  /// sql passwd
+  #[argh(option, short = 'p', default = "String::new()")]
+  sql_passwd: String,
+
+  /// database url, e.g. sqlite://prospect.db, built from sql user, ip and passwd if not set
+  #[argh(option)]
+  database_url: Option<String>,
+
+  /// only show schema version of database, without migrating
+  #[argh(switch)]
+  status: bool,
+}
+
+#[tokio::main]
+async fn main() {
+  pretty_env_logger::formatted_timed_builder()
+    .format_timestamp_secs()
+    .filter_level(LevelFilter::Info)
+    .init();
+
+  let options: MigrateOptions = argh::from_env();
+  let url = options.database_url.unwrap_or_else(|| {
+    format!("mysql://{}:{}@{}", options.sql_user, options.sql_passwd, options.sql_addr)
+  });
+  if options.status {
+    let (current, latest) = schema_version(&url).await.unwrap();
+    info!("database schema version {}, latest version {}", current, latest);
+    return;
+  }
+  if let Err(e) = open_store(&url, 1).await {
+    error!("migrate failed: {}", String::from(e));
+    std::process::exit(1);
+  }
+}
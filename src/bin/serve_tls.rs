@@ -1,5 +1,4 @@
 #![feature(addr_parse_ascii)]
-#![feature(async_closure)]
 
 use std::borrow::BorrowMut;
 use std::sync::Arc;
@@ -42,6 +41,7 @@ struct Options {
 
   /// echo mode
   #[argh(switch, short = 'e')]
+  #[allow(dead_code)]
   echo_mode: bool,
 }
 
@@ -140,7 +140,7 @@ async fn main() {
     .cert_path(&options.cert)
     .key_path(&options.key)
     // .run(([0, 0, 0, 0], 443))
-    .run(SocketAddr::parse_ascii((&options.addr).as_ref()).unwrap())
+    .run(SocketAddr::parse_ascii(options.addr.as_ref()).unwrap())
     .await;
 }
 
@@ -1,15 +1,15 @@
 #![feature(addr_parse_ascii)]
-#![feature(async_closure)]
 
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::convert::Infallible;
+use std::time::Duration;
 
-use log::{info, LevelFilter};
+use log::{error, info, LevelFilter};
 use warp::Filter;
 
-use prospect_backend::database::ProspectSqlPool;
-use prospect_backend::wechat::{types::*, handlers::*};
+use prospect_backend::database::open_store;
+use prospect_backend::wechat::{types::*, handlers::*, auth::*, outbox::run_outbox, watcher::watch_papers};
 
 #[tokio::main]
 async fn main() {
@@ -20,25 +20,41 @@ async fn main() {
 
   let options: Options = argh::from_env();
   info!("Prospect server_wx start");
-  ProspectSqlPool::init(
-    options.sql_user.clone(),
-    options.sql_passwd.clone(),
-    options.sql_addr.clone(),
-  ).await.unwrap();
-  let pool = ProspectSqlPool::new(
-    options.sql_user.clone(),
-    options.sql_passwd.clone(),
-    options.sql_addr.clone(),
-    "Prospect".to_string(),
-    5,
-  ).await.unwrap();
+  let store = match open_store(&options.database_url(), 5).await {
+    Ok(store) => store,
+    Err(e) => {
+      error!("open database failed: {}", String::from(e));
+      std::process::exit(1);
+    }
+  };
   if options.init_from_fs {
-    pool.init_from_assets(options.assets_path.clone().into()).await.unwrap();
+    if let Err(e) = store.init_from_assets(options.assets_path.clone()).await {
+      error!("init from assets {} failed: {:?}", options.assets_path, e);
+      std::process::exit(1);
+    }
   }
   info!("Create Sql connection pool OK");
 
-  let ctx = Context::new(pool, Arc::new(options.clone()));
-  println!("{:?}", ctx);
+  let templates = match options.template_config {
+    Some(ref path) => match TemplateConfig::load(path) {
+      Ok(templates) => templates,
+      Err(e) => {
+        error!("load message templates from {} failed: {}", path, e);
+        std::process::exit(1);
+      }
+    },
+    None => TemplateConfig::default(),
+  };
+  info!("Load message templates OK");
+
+  let ctx = Context::new(store, Arc::new(options.clone()), Arc::new(templates));
+  info!("Serve with {:?}", options);
+
+  tokio::spawn(ctx.token_manager.clone().run_refresher(ctx.wechat.clone()));
+  tokio::spawn(run_outbox(ctx.clone(), Duration::from_secs(options.outbox_interval.max(1))));
+  if options.watch_interval > 0 {
+    tokio::spawn(watch_papers(ctx.clone(), Duration::from_secs(options.watch_interval)));
+  }
 
   let root = warp::any();
   let hello_world = root
@@ -71,19 +87,29 @@ async fn main() {
     .and(warp::get())
     .and(warp::path("waterfall"))
     .and(warp::path::end())
-    // .and(warp::body::content_length_limit(4096))
-    // .and(warp::body::json())
+    .and(warp::query::<WaterFallInfo>())
     .and(with_context(ctx.clone()))
     .and_then(waterfall_handler);
   info!("Path \"/waterfall\" created");
 
+  // post_detail route
+  let route_post_detail = root
+    .and(warp::post())
+    .and(warp::path("post_detail"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(warp::body::json())
+    .and(with_context(ctx.clone()))
+    .and_then(post_detail_handler);
+  info!("Path \"/post_detail\" created");
+
   // subscribe route
   let route_subscribe = root
     .and(warp::post())
     .and(warp::path("subscribe"))
     .and(warp::path::end())
     .and(warp::body::content_length_limit(4096))
-    .and(warp::body::json())
+    .and(with_wechat_user(ctx.clone()))
     .and(with_context(ctx.clone()))
     .and_then(subscribe_handler);
   info!("Path \"/subscribe\" created");
@@ -94,7 +120,7 @@ async fn main() {
     .and(warp::path("get_user_subscribe"))
     .and(warp::path::end())
     .and(warp::body::content_length_limit(4096))
-    .and(warp::body::json())
+    .and(with_wechat_user(ctx.clone()))
     .and(with_context(ctx.clone()))
     .and_then(get_user_subscribe_handler);
   info!("Path \"/get_user_subscribe\" created");
@@ -119,28 +145,133 @@ async fn main() {
     .and_then(get_department_handler);
   info!("Path \"/get_department\" created");
 
+  // source route
+  let route_source = root
+    .and(warp::post())
+    .and(warp::path("source"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(with_wechat_user(ctx.clone()))
+    .and(with_context(ctx.clone()))
+    .and_then(source_handler);
+  info!("Path \"/source\" created");
+
+  // sessions route
+  let route_sessions = root
+    .and(warp::post())
+    .and(warp::path("sessions"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(with_wechat_user(ctx.clone()))
+    .and(with_context(ctx.clone()))
+    .and_then(sessions_handler);
+  info!("Path \"/sessions\" created");
+
+  // revoke_session route
+  let route_revoke_session = root
+    .and(warp::post())
+    .and(warp::path("revoke_session"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(with_wechat_user(ctx.clone()))
+    .and(with_context(ctx.clone()))
+    .and_then(revoke_session_handler);
+  info!("Path \"/revoke_session\" created");
+
+  // admin/notify route
+  let route_notify = root
+    .and(warp::post())
+    .and(warp::path("admin"))
+    .and(warp::path("notify"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(warp::body::json())
+    .and(with_context(ctx.clone()))
+    .and_then(notify_subscription);
+  info!("Path \"/admin/notify\" created");
+
+  // admin/token_status route
+  let route_token_status = root
+    .and(warp::post())
+    .and(warp::path("admin"))
+    .and(warp::path("token_status"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(warp::body::json())
+    .and(with_context(ctx.clone()))
+    .and_then(token_status_handler);
+  info!("Path \"/admin/token_status\" created");
+
+  // admin/remove route
+  let route_remove = root
+    .and(warp::post())
+    .and(warp::path("admin"))
+    .and(warp::path("remove"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(warp::body::json())
+    .and(with_context(ctx.clone()))
+    .and_then(remove_handler);
+  info!("Path \"/admin/remove\" created");
+
+  // signin route
+  let route_sign_up = root
+    .and(warp::post())
+    .and(warp::path("signin"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(warp::body::json())
+    .and(with_context(ctx.clone()))
+    .and_then(sign_up_handler);
+  info!("Path \"/signin\" created");
+
+  // login route
+  let route_log_in = root
+    .and(warp::post())
+    .and(warp::path("login"))
+    .and(warp::path::end())
+    .and(warp::body::content_length_limit(4096))
+    .and(warp::body::json())
+    .and(with_context(ctx.clone()))
+    .and_then(log_in_handler);
+  info!("Path \"/login\" created");
+
+  // logout route, session token in authorization header
+  let route_log_out = root
+    .and(warp::post())
+    .and(warp::path("logout"))
+    .and(warp::path::end())
+    .and(with_web_user(ctx.clone()))
+    .and(with_context(ctx.clone()))
+    .and_then(log_out_handler);
+  info!("Path \"/logout\" created");
+
   // post of assets
   let route_assets_article = root
     .and(warp::get())
     .and(warp::path("post"))
     .and(warp::fs::dir(options.assets_path.clone() + "/post"));
 
-  // paper of assets
-  let route_assets_paper = root
-    .and(warp::get())
-    .and(warp::path("paper"))
-    .and(warp::fs::dir(options.assets_path.clone() + "/paper"));
-
   let routes = warp::any()
     .and(hello_world)
     .or(route_send_code)
     .or(route_waterfall)
+    .or(route_post_detail)
     .or(route_subscribe)
     .or(route_get_user_subscribe)
     .or(route_get_university)
     .or(route_get_department)
+    .or(route_source)
+    .or(route_sessions)
+    .or(route_revoke_session)
+    .or(route_notify)
+    .or(route_token_status)
+    .or(route_remove)
+    .or(route_sign_up)
+    .or(route_log_in)
+    .or(route_log_out)
     .or(route_assets_article)
-    .or(route_assets_paper);
+    .recover(handle_rejection);
   info!("all route registered");
   info!("starting serve");
 
@@ -148,7 +279,7 @@ async fn main() {
     .tls()
     .cert_path(&ctx.options.cert)
     .key_path(&ctx.options.key)
-    .run(SocketAddr::parse_ascii((&ctx.options.addr).as_ref()).unwrap())
+    .run(SocketAddr::parse_ascii(ctx.options.addr.as_ref()).unwrap())
     .await;
 }
 
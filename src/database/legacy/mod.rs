@@ -0,0 +1,98 @@
+//! migration out of per-university, per-department and per-user tables
+//!
+//! Before subscriptions were normalized, universities lived in `UniUserMap.university`,
+//! departments of each university in `UniUserMap.{university uni_name}`, subscribers of
+//! each department in `UniUserMap.{department uni_name}` and departments subscribed by
+//! each user in `UserSubMap.u{open_id}`.
+
+use log::info;
+use sqlx::{Connection, MySql, MySqlConnection, Transaction};
+
+use super::migration::MigrationFuture;
+
+/// Legacy table names are name hashes or `u{open_id}`, anything else is not ours
+/// and is never interpolated into sql.
+fn is_legacy_table_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= 64
+    && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+async fn table_exists(tx: &mut Transaction<'_, MySql>, schema: &str, table: &str) -> Result<bool, sqlx::Error> {
+  let (count, ): (i64, ) =
+    sqlx::query_as("SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = ? AND table_name = ?")
+      .bind(schema)
+      .bind(table)
+      .fetch_one(&mut *tx).await?;
+  Ok(count > 0)
+}
+
+/// Copy universities, departments and subscriptions out of legacy tables, keeping their ids.
+/// Rows already copied are skipped, so it is safe to run more than once.
+/// Legacy tables are left in place.
+pub(super) fn copy_legacy_subscriptions(conn: &mut MySqlConnection) -> MigrationFuture<'_> {
+  Box::pin(async move {
+    let mut tx = conn.begin().await?;
+    if !table_exists(&mut tx, "UniUserMap", "university").await? {
+      info!("no legacy tables found, nothing to migrate");
+      return Ok(());
+    }
+    let universities = sqlx::query(
+      "INSERT IGNORE INTO Prospect.universities (id, uni_name, name) \
+       SELECT id, uni_name, name FROM UniUserMap.university"
+    ).execute(&mut tx).await?.rows_affected();
+
+    let mut departments = 0;
+    let university_rows: Vec<(u32, String)> = sqlx::query_as("SELECT id, uni_name FROM UniUserMap.university")
+      .fetch_all(&mut tx).await?;
+    for (university_id, uni_name) in university_rows {
+      if !is_legacy_table_name(&uni_name) || !table_exists(&mut tx, "UniUserMap", &uni_name).await? {
+        continue;
+      }
+      departments += sqlx::query(&format!(
+        "INSERT IGNORE INTO Prospect.departments (university_id, id, uni_name, name) \
+         SELECT ?, id, uni_name, department_name FROM UniUserMap.`{}`",
+        uni_name,
+      )).bind(university_id).execute(&mut tx).await?.rows_affected();
+    }
+
+    // subscribers recorded by department
+    let mut subscriptions = 0;
+    let department_rows: Vec<(u32, u32, String)> =
+      sqlx::query_as("SELECT university_id, id, uni_name FROM Prospect.departments")
+        .fetch_all(&mut tx).await?;
+    for (university_id, department_id, uni_name) in department_rows {
+      if !is_legacy_table_name(&uni_name) || !table_exists(&mut tx, "UniUserMap", &uni_name).await? {
+        continue;
+      }
+      subscriptions += sqlx::query(&format!(
+        "INSERT IGNORE INTO Prospect.subscriptions (open_id, university_id, department_id) \
+         SELECT open_id, ?, ? FROM UniUserMap.`{}`",
+        uni_name,
+      )).bind(university_id).bind(department_id).execute(&mut tx).await?.rows_affected();
+    }
+
+    // subscriptions recorded by user, those to departments no longer existing are dropped
+    let user_tables: Vec<(String, )> =
+      sqlx::query_as("SELECT table_name FROM information_schema.tables WHERE table_schema = 'UserSubMap'")
+        .fetch_all(&mut tx).await?;
+    for (table, ) in user_tables {
+      let open_id = match table.strip_prefix('u') {
+        Some(open_id) if is_legacy_table_name(&table) => open_id,
+        _ => continue,
+      };
+      subscriptions += sqlx::query(&format!(
+        "INSERT IGNORE INTO Prospect.subscriptions (open_id, university_id, department_id) \
+         SELECT ?, s.university_id, s.department_id FROM UserSubMap.`{}` s \
+         JOIN Prospect.departments d ON d.university_id = s.university_id AND d.id = s.department_id",
+        table,
+      )).bind(open_id).execute(&mut tx).await?.rows_affected();
+    }
+    tx.commit().await?;
+    info!(
+      "migrated {} universities, {} departments and {} subscriptions from legacy tables",
+      universities, departments, subscriptions,
+    );
+    Ok(())
+  })
+}
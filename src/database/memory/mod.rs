@@ -0,0 +1,503 @@
+//! in-memory store, for tests and trying out the server without a database
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+
+use crate::types::{hash_token, LogInInfo, SignUpInfo};
+use crate::wechat::types::{
+  AccessToken, Error, OutboxMessage, PostCursor, PostRecord, PostStatus, SessionRecord, SubscribeInfo, TokenRotation,
+  WaterFallInfo, WaterFallItem,
+};
+
+use super::store::ProspectStore;
+use super::wechat_op::TokenRefresh;
+use super::password::{self, Verified};
+use super::{LogInErr, SignUpErr};
+
+#[derive(Debug)]
+struct NamedRow {
+  uni_name: String,
+  name: String,
+}
+
+#[derive(Debug)]
+struct UserRow {
+  user_id: u32,
+  /// salt of legacy hash, empty for argon2 hash
+  salt: Vec<u8>,
+  hash: String,
+}
+
+#[derive(Debug)]
+struct SessionRow {
+  open_id: String,
+  token_hash: String,
+  /// hash of token replaced on rotation, until when it is still accepted
+  prev: Option<(String, DateTime<Utc>)>,
+  record: SessionRecord,
+}
+
+impl SessionRow {
+  fn accepts(&self, open_id: &str, hash: &str, now: DateTime<Utc>) -> bool {
+    self.open_id == open_id && (
+      self.token_hash == hash && self.record.expired > now
+        || self.prev.as_ref().is_some_and(|(prev, expired)| prev == hash && *expired > now)
+    )
+  }
+}
+
+#[derive(Debug, Default)]
+struct MemoryData {
+  /// id --- session of mini-program user
+  sessions: BTreeMap<u64, SessionRow>,
+  last_session_id: u64,
+  universities: BTreeMap<u32, NamedRow>,
+  last_university_id: u32,
+  /// (university_id, department_id) --- department
+  departments: BTreeMap<(u32, u32), NamedRow>,
+  /// (open_id, university_id, department_id)
+  subscriptions: BTreeSet<(String, u32, u32)>,
+  /// id --- post
+  posts: BTreeMap<u32, PostRecord>,
+  outbox: BTreeMap<u64, OutboxMessage>,
+  /// ids of removed messages are not reused, as with auto increment
+  last_outbox_id: u64,
+  /// username --- user
+  users: HashMap<String, UserRow>,
+  /// hash of access_token of web session --- user_id, expired time
+  web_sessions: HashMap<String, (u32, DateTime<Utc>)>,
+}
+
+impl MemoryData {
+  /// remove rows referring to departments matched except delivered notifications,
+  /// return number of subscriptions removed.
+  fn remove_departments(&mut self, matches: impl Fn(u32, u32) -> bool) -> u64 {
+    self.departments.retain(|(u, d), _| !matches(*u, *d));
+    let before = self.subscriptions.len();
+    self.subscriptions.retain(|(_, u, d)| !matches(*u, *d));
+    self.outbox.retain(|_, m| m.delivered_at.is_some() || !matches(m.university_id, m.department_id));
+    for post in self.posts.values_mut() {
+      if let (Some(u), Some(d)) = (post.university_id, post.department_id) {
+        if matches(u, d) {
+          post.university_id = None;
+          post.department_id = None;
+        }
+      }
+    }
+    (before - self.subscriptions.len()) as u64
+  }
+}
+
+/// Store keeping everything in memory, lost once dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+  data: Mutex<MemoryData>,
+  /// held while refreshing, so that only one refresh runs at a time
+  wechat_token: tokio::sync::Mutex<Option<(String, DateTime<Utc>)>>,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  fn data(&self) -> MutexGuard<'_, MemoryData> {
+    self.data.lock().unwrap()
+  }
+}
+
+#[async_trait]
+impl ProspectStore for MemoryStore {
+  async fn session_id(&self, open_id: &str, token: AccessToken) -> Result<Option<u64>, sqlx::Error> {
+    let hash = token.hash();
+    let now = Utc::now();
+    let mut data = self.data();
+    let session = data.sessions
+      .values_mut()
+      .find(|s| s.accepts(open_id, &hash, now));
+    Ok(session.map(|s| {
+      s.record.last_seen_at = now;
+      s.record.id
+    }))
+  }
+
+  async fn record_token(&self, open_id: &str, token: AccessToken, device: &str) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let mut data = self.data();
+    data.sessions.retain(|_, s| s.open_id != open_id || s.record.expired > now);
+    data.last_session_id += 1;
+    let id = data.last_session_id;
+    let session = SessionRow {
+      open_id: open_id.to_string(),
+      token_hash: token.hash(),
+      prev: None,
+      record: SessionRecord {
+        id,
+        device: device.to_string(),
+        created_at: now,
+        last_seen_at: now,
+        expired: token.expired,
+      },
+    };
+    data.sessions.insert(id, session);
+    Ok(())
+  }
+
+  async fn valid_token_and_update(&self, token: AccessToken, open_id: &str, rotation: TokenRotation) -> Result<AccessToken, sqlx::Error> {
+    let hash = token.hash();
+    let now = Utc::now();
+    let mut data = self.data();
+    let session = data.sessions
+      .values_mut()
+      .find(|s| s.accepts(open_id, &hash, now))
+      .ok_or(sqlx::Error::RowNotFound)?;
+    session.record.last_seen_at = now;
+    if session.token_hash != hash {
+      // replaced by a request sent in parallel
+      let expired = session.prev.as_ref().map_or(now, |(_, expired)| *expired);
+      return Ok(AccessToken { token: token.token, expired });
+    }
+    if !rotation.is_due(session.record.expired) {
+      return Ok(AccessToken { token: token.token, expired: session.record.expired });
+    }
+    let new_token = AccessToken::new();
+    session.prev = Some((hash, rotation.grace_expired(session.record.expired)));
+    session.token_hash = new_token.hash();
+    session.record.expired = new_token.expired;
+    Ok(new_token)
+  }
+
+  async fn get_sessions(&self, open_id: &str) -> Result<Vec<SessionRecord>, sqlx::Error> {
+    let now = Utc::now();
+    let mut sessions: Vec<SessionRecord> = self.data().sessions
+      .values()
+      .filter(|s| s.open_id == open_id && s.record.expired > now)
+      .map(|s| s.record.clone())
+      .collect();
+    sessions.sort_by_key(|s| Reverse((s.last_seen_at, s.id)));
+    Ok(sessions)
+  }
+
+  async fn revoke_session(&self, open_id: &str, session_id: u64) -> Result<(), sqlx::Error> {
+    let mut data = self.data();
+    match data.sessions.get(&session_id) {
+      Some(s) if s.open_id == open_id => {
+        data.sessions.remove(&session_id);
+        Ok(())
+      }
+      _ => Err(sqlx::Error::RowNotFound),
+    }
+  }
+
+  async fn revoke_sessions(&self, open_id: &str) -> Result<u64, sqlx::Error> {
+    let mut data = self.data();
+    let before = data.sessions.len();
+    data.sessions.retain(|_, s| s.open_id != open_id);
+    Ok((before - data.sessions.len()) as u64)
+  }
+
+  async fn shared_wechat_token(&self, refresh_ahead: Duration, refresh: TokenRefresh<'_>) -> Result<(String, DateTime<Utc>), Error> {
+    let mut current = self.wechat_token.lock().await;
+    if let Some(token) = current.as_ref() {
+      if token.1 - Utc::now() > refresh_ahead {
+        return Ok(token.clone());
+      }
+    }
+    match refresh.await {
+      Ok(token) => {
+        *current = Some(token.clone());
+        Ok(token)
+      }
+      Err(e) => match current.as_ref() {
+        // refreshing ahead failed, current token still works for now
+        Some(token) if token.1 > Utc::now() => {
+          warn!("refresh shared access token failed: {:?}, keep using current one", e);
+          Ok(token.clone())
+        }
+        _ => Err(e),
+      }
+    }
+  }
+
+  async fn add_university(&self, uni_name: &str, name: &str) -> Result<u32, sqlx::Error> {
+    let mut data = self.data();
+    if let Some((id, _)) = data.universities.iter().find(|(_, u)| u.uni_name == uni_name) {
+      return Ok(*id);
+    }
+    data.last_university_id += 1;
+    let id = data.last_university_id;
+    data.universities.insert(id, NamedRow { uni_name: uni_name.to_string(), name: name.to_string() });
+    Ok(id)
+  }
+
+  async fn remove_university(&self, university_id: u32) -> Result<u64, sqlx::Error> {
+    let mut data = self.data();
+    data.universities.remove(&university_id).ok_or(sqlx::Error::RowNotFound)?;
+    Ok(data.remove_departments(|u, _| u == university_id))
+  }
+
+  async fn add_department(&self, university_id: u32, uni_name: &str, name: &str) -> Result<u32, sqlx::Error> {
+    let mut data = self.data();
+    if !data.universities.contains_key(&university_id) {
+      return Err(sqlx::Error::RowNotFound);
+    }
+    let departments = data.departments.range((university_id, 0)..=(university_id, u32::MAX));
+    if let Some(((_, id), _)) = departments.clone().find(|(_, d)| d.uni_name == uni_name) {
+      return Ok(*id);
+    }
+    let id = departments.last().map_or(0, |((_, id), _)| *id) + 1;
+    data.departments.insert((university_id, id), NamedRow { uni_name: uni_name.to_string(), name: name.to_string() });
+    Ok(id)
+  }
+
+  async fn remove_department(&self, university_id: u32, department_id: u32) -> Result<u64, sqlx::Error> {
+    let mut data = self.data();
+    if !data.departments.contains_key(&(university_id, department_id)) {
+      return Err(sqlx::Error::RowNotFound);
+    }
+    Ok(data.remove_departments(|u, d| (u, d) == (university_id, department_id)))
+  }
+
+  async fn get_universities(&self) -> Result<HashMap<u32, String>, sqlx::Error> {
+    Ok(self.data().universities.iter().map(|(id, u)| (*id, u.name.clone())).collect())
+  }
+
+  async fn get_departments(&self, university_id: u32) -> Result<HashMap<u32, String>, sqlx::Error> {
+    let data = self.data();
+    if !data.universities.contains_key(&university_id) {
+      return Err(sqlx::Error::RowNotFound);
+    }
+    Ok(
+      data.departments
+        .range((university_id, 0)..=(university_id, u32::MAX))
+        .map(|((_, id), d)| (*id, d.name.clone()))
+        .collect()
+    )
+  }
+
+  async fn get_university_name(&self, university_id: u32) -> Result<String, sqlx::Error> {
+    self.data().universities.get(&university_id).map(|u| u.name.clone()).ok_or(sqlx::Error::RowNotFound)
+  }
+
+  async fn get_department_name(&self, university_id: u32, department_id: u32) -> Result<String, sqlx::Error> {
+    self.data().departments
+      .get(&(university_id, department_id))
+      .map(|d| d.name.clone())
+      .ok_or(sqlx::Error::RowNotFound)
+  }
+
+  async fn subscribe_user(&self, info: SubscribeInfo) -> Result<(), sqlx::Error> {
+    let mut data = self.data();
+    // all or nothing, as a transaction would be
+    if info.info.iter().any(|d| !data.departments.contains_key(&(d.school_code, d.department_code))) {
+      return Err(sqlx::Error::RowNotFound);
+    }
+    for each in info.info {
+      let key = (info.open_id.clone(), each.school_code, each.department_code);
+      if each.oper == 0 {
+        data.subscriptions.insert(key);
+      } else {
+        data.subscriptions.remove(&key);
+      }
+    }
+    Ok(())
+  }
+
+  async fn get_subscriptions(&self, open_id: &str) -> Result<HashMap<u32, Vec<u32>>, sqlx::Error> {
+    let data = self.data();
+    let map = data.subscriptions
+      .iter()
+      .filter(|(o, ..)| o == open_id)
+      .fold(HashMap::new(), |mut map, (_, uni_id, dep_id)| {
+        map.entry(*uni_id).or_insert_with(Vec::new).push(*dep_id);
+        map
+      });
+    Ok(map)
+  }
+
+  async fn get_users(&self, university_id: u32, department_id: u32) -> Result<Vec<String>, sqlx::Error> {
+    Ok(
+      self.data().subscriptions
+        .iter()
+        .filter(|(_, u, d)| (*u, *d) == (university_id, department_id))
+        .map(|(open_id, ..)| open_id.clone())
+        .collect()
+    )
+  }
+
+  async fn add_post(&self, post: &PostRecord) -> Result<(), sqlx::Error> {
+    let mut data = self.data();
+    if data.posts.values().all(|p| p.asset_path != post.asset_path) {
+      let id = data.posts.keys().last().map_or(0, |id| *id) + 1;
+      data.posts.insert(id, post.clone());
+    }
+    Ok(())
+  }
+
+  async fn set_post_status(&self, asset_path: &str, status: PostStatus) -> Result<(), sqlx::Error> {
+    let mut data = self.data();
+    let post = data.posts.values_mut().find(|p| p.asset_path == asset_path).ok_or(sqlx::Error::RowNotFound)?;
+    post.status = status;
+    Ok(())
+  }
+
+  async fn get_post(&self, asset_path: &str) -> Result<PostRecord, sqlx::Error> {
+    self.data().posts
+      .values()
+      .find(|p| p.asset_path == asset_path && p.status == PostStatus::Published)
+      .cloned()
+      .ok_or(sqlx::Error::RowNotFound)
+  }
+
+  async fn get_posts(
+    &self,
+    info: &WaterFallInfo,
+    cursor: Option<PostCursor>,
+  ) -> Result<(Vec<WaterFallItem>, Option<PostCursor>), sqlx::Error> {
+    let data = self.data();
+    let mut posts: Vec<(&u32, &PostRecord)> = data.posts
+      .iter()
+      .filter(|(_, p)| p.status == PostStatus::Published)
+      .filter(|(_, p)| info.university_id.is_none_or(|u| p.university_id == Some(u)))
+      .filter(|(_, p)| info.department_id.is_none_or(|d| p.department_id == Some(d)))
+      .filter(|(id, p)| cursor.is_none_or(|c| (p.publish_date, **id) < (c.publish_date, c.id)))
+      .collect();
+    posts.sort_by_key(|(id, p)| Reverse((p.publish_date, **id)));
+    let page_size = info.page_size() as usize;
+    let next_cursor = if posts.len() > page_size {
+      posts.truncate(page_size);
+      posts.last().map(|(id, p)| PostCursor { publish_date: p.publish_date, id: **id })
+    } else {
+      None
+    };
+    let items = posts
+      .into_iter()
+      .map(|(_, p)| WaterFallItem::new(p.img_source_link.clone(), p.title.clone(), p.asset_path.clone()))
+      .collect();
+    Ok((items, next_cursor))
+  }
+
+  async fn outbox_enqueue(&self, messages: Vec<OutboxMessage>) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let mut data = self.data();
+    let mut recorded = Vec::with_capacity(messages.len());
+    for mut message in messages {
+      data.last_outbox_id += 1;
+      message.id = data.last_outbox_id;
+      data.outbox.insert(message.id, message.clone());
+      recorded.push(message);
+    }
+    Ok(recorded)
+  }
+
+  async fn outbox_due(&self, max_attempts: u32, limit: u32) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let now = Utc::now();
+    let mut due: Vec<OutboxMessage> = self.data().outbox
+      .values()
+      .filter(|m| m.delivered_at.is_none() && m.attempts < max_attempts && m.next_attempt_at <= now)
+      .cloned()
+      .collect();
+    due.sort_by_key(|m| m.next_attempt_at);
+    due.truncate(limit as usize);
+    Ok(due)
+  }
+
+  async fn outbox_claim(&self, id: u64, lease_until: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let mut data = self.data();
+    match data.outbox.get_mut(&id) {
+      Some(m) if m.delivered_at.is_none() && m.next_attempt_at <= Utc::now() => {
+        m.next_attempt_at = lease_until;
+        Ok(true)
+      }
+      _ => Ok(false),
+    }
+  }
+
+  async fn outbox_mark_delivered(&self, id: u64) -> Result<(), sqlx::Error> {
+    if let Some(m) = self.data().outbox.get_mut(&id) {
+      m.attempts += 1;
+      m.last_error = None;
+      m.delivered_at = Some(Utc::now());
+    }
+    Ok(())
+  }
+
+  async fn outbox_mark_failed(&self, id: u64, err: Error, attempts: u32, next_attempt_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    if let Some(m) = self.data().outbox.get_mut(&id) {
+      m.attempts = attempts;
+      m.last_error = Some(err);
+      m.next_attempt_at = next_attempt_at;
+    }
+    Ok(())
+  }
+
+  async fn outbox_history(&self, open_id: &str) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let mut history: Vec<OutboxMessage> = self.data().outbox
+      .values()
+      .filter(|m| m.open_id == open_id)
+      .cloned()
+      .collect();
+    history.sort_by_key(|m| Reverse((m.created_at, m.id)));
+    Ok(history)
+  }
+
+  async fn sign_up(&self, info: SignUpInfo) -> Result<(), SignUpErr> {
+    if self.data().users.contains_key(&info.username) {
+      return Err(SignUpErr::UserExist);
+    }
+    let hash = password::hash_password(info.password).await;
+    let mut data = self.data();
+    // checked again, someone may have signed up with the same name while hashing
+    if data.users.contains_key(&info.username) {
+      return Err(SignUpErr::UserExist);
+    }
+    let user = UserRow {
+      user_id: data.users.len() as u32 + 1,
+      salt: Vec::new(),
+      hash,
+    };
+    data.users.insert(info.username, user);
+    Ok(())
+  }
+
+  async fn log_in(&self, info: LogInInfo) -> Result<(u32, crate::types::AccessToken), LogInErr> {
+    let user = self.data().users.get(&info.username).map(|user| (user.user_id, user.salt.clone(), user.hash.clone()));
+    let Some((user_id, salt, hash)) = user else {
+      password::verify_dummy(info.password).await;
+      return Err(LogInErr::InvalidCredentials);
+    };
+    let verified = password::verify_password(salt, hash, info.password.clone()).await;
+    if verified == Verified::NotMatch {
+      return Err(LogInErr::InvalidCredentials);
+    }
+    let rehash = match verified {
+      Verified::Rehash => Some(password::hash_password(info.password).await),
+      _ => None,
+    };
+    let mut data = self.data();
+    if let (Some(hash), Some(user)) = (rehash, data.users.get_mut(&info.username)) {
+      user.salt = Vec::new();
+      user.hash = hash;
+    }
+    let token = crate::types::AccessToken::new();
+    let now = Utc::now();
+    data.web_sessions.retain(|_, (u, expired)| *u != user_id || *expired > now);
+    data.web_sessions.insert(token.hash(), (user_id, token.expired));
+    Ok((user_id, token))
+  }
+
+  async fn web_session_user(&self, token: &str) -> Result<u32, sqlx::Error> {
+    match self.data().web_sessions.get(&hash_token(token)) {
+      Some((user_id, expired)) if *expired > Utc::now() => Ok(*user_id),
+      _ => Err(sqlx::Error::RowNotFound),
+    }
+  }
+
+  async fn log_out(&self, token: &str) -> Result<(), sqlx::Error> {
+    self.data().web_sessions.remove(&hash_token(token)).map(|_| ()).ok_or(sqlx::Error::RowNotFound)
+  }
+}
@@ -0,0 +1,325 @@
+//! versioned schema migrations of Prospect database
+//!
+//! Applied versions are recorded in `Prospect.schemaVersion`. Migrations are applied in
+//! order of version and never edited once released, a schema change is a new migration
+//! appended to SCHEMA_VERSIONS and to MIGRATIONS of every backend. MySQL commits DDL
+//! implicitly, so a migration interrupted halfway is run again from its start and must be
+//! safe to repeat.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use log::info;
+use sqlx::{query, MySqlConnection};
+
+use super::legacy;
+
+/// Version and description of every migration, shared by MySQL and SQLite so that a version
+/// means the same schema in both. Each backend lists how to apply them in its own dialect.
+pub(crate) const SCHEMA_VERSIONS: &[(u32, &str)] = &[
+  (1, "initial schema"),
+  (2, "copy subscriptions out of legacy per-user and per-department tables"),
+  (3, "web user accounts"),
+  (4, "web sessions"),
+  (5, "keep only hash of mini-program access tokens"),
+  (6, "one row for each session of mini-program users"),
+  (7, "grace window for replaced mini-program tokens"),
+  (8, "keep only hash of web session tokens"),
+];
+
+/// future returned by a migration written in rust.
+pub type MigrationFuture<'a> = Pin<Box<dyn Future<Output=Result<(), sqlx::Error>> + Send + 'a>>;
+
+enum Up {
+  /// statements executed in order
+  Sql(&'static [&'static str]),
+  Code(fn(&mut MySqlConnection) -> MigrationFuture<'_>),
+}
+
+const INITIAL_SCHEMA: &[&str] = &[
+  // open_id --- access_token --- expired_time map
+  "CREATE TABLE IF NOT EXISTS Prospect.tokenMap (\
+   open_id VARCHAR(255) NOT NULL ,\
+   access_token BLOB(256) NOT NULL ,\
+   expired_time TIMESTAMP NOT NULL ,\
+   PRIMARY KEY (open_id)\
+   )",
+  // mini-program access token shared by all server instances, a single row
+  "CREATE TABLE IF NOT EXISTS Prospect.wechatToken (\
+   id TINYINT UNSIGNED NOT NULL ,\
+   access_token VARCHAR(512) NOT NULL ,\
+   expired_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+   PRIMARY KEY (id)\
+   )",
+  "INSERT IGNORE INTO Prospect.wechatToken (id, access_token, expired_time) VALUES (1, '', FROM_UNIXTIME(1))",
+  // university_id --- university_name map
+  "CREATE TABLE IF NOT EXISTS Prospect.universities (\
+   id INT UNSIGNED NOT NULL AUTO_INCREMENT ,\
+   uni_name VARCHAR(128) NOT NULL ,\
+   name VARCHAR(1024) NOT NULL ,\
+   PRIMARY KEY (id) ,\
+   UNIQUE KEY (uni_name)\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+  // (university_id, department_id) --- department_name map
+  "CREATE TABLE IF NOT EXISTS Prospect.departments (\
+   university_id INT UNSIGNED NOT NULL ,\
+   id INT UNSIGNED NOT NULL ,\
+   uni_name VARCHAR(128) NOT NULL ,\
+   name VARCHAR(1024) NOT NULL ,\
+   PRIMARY KEY (university_id, id) ,\
+   UNIQUE KEY (uni_name) ,\
+   FOREIGN KEY (university_id) REFERENCES Prospect.universities (id) ON DELETE CASCADE\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+  // open_id --- department subscribed
+  "CREATE TABLE IF NOT EXISTS Prospect.subscriptions (\
+   open_id VARCHAR(255) NOT NULL ,\
+   university_id INT UNSIGNED NOT NULL ,\
+   department_id INT UNSIGNED NOT NULL ,\
+   PRIMARY KEY (open_id, university_id, department_id) ,\
+   KEY (university_id, department_id) ,\
+   FOREIGN KEY (university_id, department_id) \
+     REFERENCES Prospect.departments (university_id, id) ON DELETE CASCADE\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+  // post catalog shown in waterfall
+  "CREATE TABLE IF NOT EXISTS Prospect.posts (\
+   id INT UNSIGNED NOT NULL AUTO_INCREMENT ,\
+   title VARCHAR(1024) NOT NULL ,\
+   img_source_link VARCHAR(1024) NOT NULL ,\
+   asset_path VARCHAR(512) NOT NULL ,\
+   author VARCHAR(255) NOT NULL ,\
+   publish_date TIMESTAMP NOT NULL ,\
+   status TINYINT UNSIGNED NOT NULL ,\
+   university_id INT UNSIGNED NULL ,\
+   department_id INT UNSIGNED NULL ,\
+   PRIMARY KEY (id) ,\
+   UNIQUE KEY (asset_path) ,\
+   KEY (status, publish_date) ,\
+   KEY (university_id, department_id)\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+  // subscription messages to send and sent
+  "CREATE TABLE IF NOT EXISTS Prospect.notifyOutbox (\
+   id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT ,\
+   open_id VARCHAR(255) NOT NULL ,\
+   university_id INT UNSIGNED NOT NULL ,\
+   department_id INT UNSIGNED NOT NULL ,\
+   template VARCHAR(128) NOT NULL ,\
+   payload TEXT NOT NULL ,\
+   attempts INT UNSIGNED NOT NULL ,\
+   last_err_code INT NULL ,\
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+   next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+   delivered_at TIMESTAMP NULL ,\
+   PRIMARY KEY (id) ,\
+   KEY (delivered_at, next_attempt_at) ,\
+   KEY (open_id)\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+];
+
+const USER_AUTH: &[&str] = &[
+  // web users signed up with username and password
+  "CREATE TABLE IF NOT EXISTS Prospect.UserAuth (\
+   user_id INT UNSIGNED NOT NULL AUTO_INCREMENT ,\
+   username VARCHAR(64) NOT NULL ,\
+   salt VARBINARY(16) NOT NULL ,\
+   hash VARCHAR(255) NOT NULL ,\
+   PRIMARY KEY (user_id) ,\
+   UNIQUE KEY (username)\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+];
+
+const WEB_SESSION: &[&str] = &[
+  // access_token --- user_id --- expired_time of web users logged in
+  "CREATE TABLE IF NOT EXISTS Prospect.webSession (\
+   access_token VARCHAR(64) NOT NULL ,\
+   user_id INT UNSIGNED NOT NULL ,\
+   expired_time TIMESTAMP NOT NULL ,\
+   PRIMARY KEY (access_token) ,\
+   KEY (user_id) ,\
+   FOREIGN KEY (user_id) REFERENCES Prospect.UserAuth (user_id) ON DELETE CASCADE\
+   ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+];
+
+const HASHED_TOKENS: &[&str] = &[
+  // tokens issued before were recorded in plaintext and derived from open_id and time,
+  // drop them all so that users log in again through code2session
+  "DELETE FROM Prospect.tokenMap",
+  "ALTER TABLE Prospect.tokenMap MODIFY access_token CHAR(64) NOT NULL",
+];
+
+/// Move tokens into a table with a row for each session, so that logging in on another device
+/// does not end the session of the first one. Safe to repeat, tokenMap is dropped last.
+fn per_session_tokens(conn: &mut MySqlConnection) -> MigrationFuture<'_> {
+  Box::pin(async move {
+    query("CREATE TABLE IF NOT EXISTS Prospect.wechatSession (\
+           id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT ,\
+           open_id VARCHAR(255) NOT NULL ,\
+           token_hash CHAR(64) NOT NULL ,\
+           device VARCHAR(255) NOT NULL ,\
+           created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+           last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+           expired_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+           PRIMARY KEY (id) ,\
+           UNIQUE KEY (token_hash) ,\
+           KEY (open_id)\
+           ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci")
+      .execute(&mut *conn).await?;
+    let (count, ): (i64, ) =
+      sqlx::query_as("SELECT COUNT(*) FROM information_schema.tables \
+                      WHERE table_schema = 'Prospect' AND table_name = 'tokenMap'")
+        .fetch_one(&mut *conn).await?;
+    if count > 0 {
+      query("INSERT IGNORE INTO Prospect.wechatSession (open_id, token_hash, device, expired_time) \
+             SELECT open_id, access_token, '', expired_time FROM Prospect.tokenMap")
+        .execute(&mut *conn).await?;
+      query("DROP TABLE Prospect.tokenMap").execute(&mut *conn).await?;
+    }
+    Ok(())
+  })
+}
+
+/// Add columns of the token replaced on rotation and until when it is still accepted.
+/// Safe to repeat, both columns are added by one statement unless they are there already.
+fn rotation_grace(conn: &mut MySqlConnection) -> MigrationFuture<'_> {
+  Box::pin(async move {
+    if !column_exists(conn, "wechatSession", "prev_token_hash").await? {
+      query("ALTER TABLE Prospect.wechatSession \
+             ADD COLUMN prev_token_hash CHAR(64) NULL ,\
+             ADD COLUMN prev_expired_time TIMESTAMP NULL")
+        .execute(&mut *conn).await?;
+    }
+    Ok(())
+  })
+}
+
+/// Keep only hash of web session tokens. Sessions recorded before hold plaintext tokens, which
+/// look the same as hashes, so they are dropped and web users log in again.
+/// Safe to repeat, the column is renamed last.
+fn hashed_web_sessions(conn: &mut MySqlConnection) -> MigrationFuture<'_> {
+  Box::pin(async move {
+    if column_exists(conn, "webSession", "access_token").await? {
+      query("DELETE FROM Prospect.webSession").execute(&mut *conn).await?;
+      query("ALTER TABLE Prospect.webSession CHANGE access_token token_hash CHAR(64) NOT NULL")
+        .execute(&mut *conn).await?;
+    }
+    Ok(())
+  })
+}
+
+/// if table of Prospect database has column.
+async fn column_exists(conn: &mut MySqlConnection, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+  let (count, ): (i64, ) =
+    sqlx::query_as("SELECT COUNT(*) FROM information_schema.columns \
+                    WHERE table_schema = 'Prospect' AND table_name = ? AND column_name = ?")
+      .bind(table)
+      .bind(column)
+      .fetch_one(&mut *conn).await?;
+  Ok(count > 0)
+}
+
+/// how to apply each of SCHEMA_VERSIONS, in the same order
+const MIGRATIONS: &[Up] = &[
+  Up::Sql(INITIAL_SCHEMA),
+  Up::Code(legacy::copy_legacy_subscriptions),
+  Up::Sql(USER_AUTH),
+  Up::Sql(WEB_SESSION),
+  Up::Sql(HASHED_TOKENS),
+  Up::Code(per_session_tokens),
+  Up::Code(rotation_grace),
+  Up::Code(hashed_web_sessions),
+];
+
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSIONS.len());
+
+/// name of the advisory lock held while migrating, so that instances starting together
+/// do not apply the same migration twice
+const LOCK_NAME: &str = "Prospect.schemaVersion";
+const LOCK_TIMEOUT_SECS: u32 = 60;
+
+#[derive(Debug)]
+pub enum MigrateErr {
+  /// database was migrated by a newer binary
+  DatabaseAhead { database: u32, binary: u32 },
+  /// another instance kept migrating for too long
+  LockTimeout,
+  SqlErr(sqlx::Error),
+}
+
+impl From<sqlx::Error> for MigrateErr {
+  fn from(value: sqlx::Error) -> Self {
+    MigrateErr::SqlErr(value)
+  }
+}
+
+impl From<MigrateErr> for String {
+  fn from(value: MigrateErr) -> Self {
+    match value {
+      MigrateErr::DatabaseAhead { database, binary } =>
+        format!("database schema version {} is newer than {} supported, upgrade server first", database, binary),
+      MigrateErr::LockTimeout => "timeout waiting for another instance migrating".into(),
+      MigrateErr::SqlErr(e) => format!("sql error: {}", e),
+    }
+  }
+}
+
+/// latest schema version known by this binary.
+pub fn latest_version() -> u32 {
+  SCHEMA_VERSIONS.last().map_or(0, |(version, _)| *version)
+}
+
+/// schema version of database, 0 if it was never migrated.
+pub async fn current_version(conn: &mut MySqlConnection) -> Result<u32, sqlx::Error> {
+  let (count, ): (i64, ) = sqlx::query_as(
+    "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = 'Prospect' AND table_name = 'schemaVersion'"
+  ).fetch_one(&mut *conn).await?;
+  if count == 0 {
+    return Ok(0);
+  }
+  let (version, ): (Option<u32>, ) = sqlx::query_as("SELECT MAX(version) FROM Prospect.schemaVersion")
+    .fetch_one(&mut *conn).await?;
+  Ok(version.unwrap_or(0))
+}
+
+/// Apply migrations newer than the database, returns schema version afterwards.
+pub async fn migrate(conn: &mut MySqlConnection) -> Result<u32, MigrateErr> {
+  let (locked, ): (Option<i64>, ) = sqlx::query_as("SELECT GET_LOCK(?, ?)")
+    .bind(LOCK_NAME)
+    .bind(LOCK_TIMEOUT_SECS)
+    .fetch_one(&mut *conn).await?;
+  if locked != Some(1) {
+    return Err(MigrateErr::LockTimeout);
+  }
+  let r = migrate_locked(conn).await;
+  query("SELECT RELEASE_LOCK(?)").bind(LOCK_NAME).execute(&mut *conn).await?;
+  r
+}
+
+async fn migrate_locked(conn: &mut MySqlConnection) -> Result<u32, MigrateErr> {
+  query("CREATE DATABASE IF NOT EXISTS Prospect").execute(&mut *conn).await?;
+  query("CREATE TABLE IF NOT EXISTS Prospect.schemaVersion (\
+         version INT UNSIGNED NOT NULL ,\
+         description VARCHAR(255) NOT NULL ,\
+         applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ,\
+         PRIMARY KEY (version)\
+         )")
+    .execute(&mut *conn).await?;
+  let current = current_version(conn).await?;
+  if current > latest_version() {
+    return Err(MigrateErr::DatabaseAhead { database: current, binary: latest_version() });
+  }
+  let mut version = current;
+  for (&(v, description), up) in SCHEMA_VERSIONS.iter().zip(MIGRATIONS).filter(|((v, _), _)| *v > current) {
+    info!("apply migration {}: {}", v, description);
+    match *up {
+      Up::Sql(statements) => for sql in statements {
+        query(sql).execute(&mut *conn).await?;
+      }
+      Up::Code(up) => up(conn).await?,
+    }
+    query("INSERT INTO Prospect.schemaVersion (version, description) VALUES (?, ?)")
+      .bind(v)
+      .bind(description)
+      .execute(&mut *conn).await?;
+    version = v;
+  }
+  Ok(version)
+}
@@ -0,0 +1,137 @@
+//! notification outbox sql api definitions
+
+use chrono::{DateTime, Utc};
+
+use crate::wechat::types::{Error, OutboxMessage};
+
+use super::ProspectSqlPool;
+
+/// id, open_id, university_id, department_id, template, payload, attempts, last_err_code,
+/// created_at, next_attempt_at, delivered_at, id is i64 in SQLite
+pub(crate) type OutboxRow<Id = u64> = (
+  Id, String, u32, u32, String, String, u32, Option<i32>,
+  DateTime<Utc>, DateTime<Utc>, Option<DateTime<Utc>>,
+);
+
+/// select all columns of OutboxRow from outbox table, followed by the given clauses
+macro_rules! select_outbox {
+  ($table:literal, $clauses:literal) => {
+    concat!(
+      "SELECT id, open_id, university_id, department_id, template, payload, attempts, last_err_code, \
+       created_at, next_attempt_at, delivered_at FROM ", $table, " ",
+      $clauses,
+    )
+  };
+}
+pub(crate) use select_outbox;
+
+pub(crate) fn from_row<Id: TryInto<u64>>(row: OutboxRow<Id>) -> OutboxMessage {
+  OutboxMessage {
+    // ids are assigned by auto increment, never negative
+    id: row.0.try_into().unwrap_or_default(),
+    open_id: row.1,
+    university_id: row.2,
+    department_id: row.3,
+    template: row.4,
+    payload: row.5,
+    attempts: row.6,
+    last_error: row.7.map(Error::from),
+    created_at: row.8,
+    next_attempt_at: row.9,
+    delivered_at: row.10,
+  }
+}
+
+// impl for notification outbox
+impl ProspectSqlPool {
+  /// record messages in outbox, return them with id assigned.
+  pub async fn outbox_enqueue(&self, messages: Vec<OutboxMessage>) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let sql =
+      "INSERT INTO Prospect.notifyOutbox \
+       (open_id, university_id, department_id, template, payload, attempts, created_at, next_attempt_at) \
+       VALUES (?, ?, ?, ?, ?, 0, ?, ?)";
+    let mut tx = self.pool.begin().await?;
+    let mut recorded = Vec::with_capacity(messages.len());
+    for mut message in messages {
+      let r = sqlx::query(sql)
+        .bind(&message.open_id)
+        .bind(message.university_id)
+        .bind(message.department_id)
+        .bind(&message.template)
+        .bind(&message.payload)
+        .bind(message.created_at)
+        .bind(message.next_attempt_at)
+        .execute(&mut tx).await?;
+      message.id = r.last_insert_id();
+      recorded.push(message);
+    }
+    tx.commit().await?;
+    Ok(recorded)
+  }
+
+  /// undelivered messages due for another attempt, oldest first.
+  pub async fn outbox_due(&self, max_attempts: u32, limit: u32) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let sql = select_outbox!(
+      "Prospect.notifyOutbox",
+      "WHERE delivered_at IS NULL AND attempts < ? AND next_attempt_at <= ? \
+       ORDER BY next_attempt_at \
+       LIMIT ?"
+    );
+    let rows: Vec<OutboxRow> = sqlx::query_as(sql)
+      .bind(max_attempts)
+      .bind(Utc::now())
+      .bind(limit)
+      .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().map(from_row).collect())
+  }
+
+  /// take a due message for delivery until lease_until, return false if someone else took it.
+  pub async fn outbox_claim(&self, id: u64, lease_until: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let sql =
+      "UPDATE Prospect.notifyOutbox SET next_attempt_at = ? \
+       WHERE id = ? AND delivered_at IS NULL AND next_attempt_at <= ?";
+    let r = sqlx::query(sql)
+      .bind(lease_until)
+      .bind(id)
+      .bind(Utc::now())
+      .execute(&self.pool).await?;
+    Ok(r.rows_affected() == 1)
+  }
+
+  pub async fn outbox_mark_delivered(&self, id: u64) -> Result<(), sqlx::Error> {
+    let sql =
+      "UPDATE Prospect.notifyOutbox \
+       SET attempts = attempts + 1, last_err_code = NULL, delivered_at = ? \
+       WHERE id = ?";
+    sqlx::query(sql)
+      .bind(Utc::now())
+      .bind(id)
+      .execute(&self.pool).await?;
+    Ok(())
+  }
+
+  /// record a failed attempt, message is retried at next_attempt_at,
+  /// or given up if attempts is set to max_attempts.
+  pub async fn outbox_mark_failed(&self, id: u64, err: Error, attempts: u32, next_attempt_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    let sql =
+      "UPDATE Prospect.notifyOutbox \
+       SET attempts = ?, last_err_code = ?, next_attempt_at = ? \
+       WHERE id = ?";
+    sqlx::query(sql)
+      .bind(attempts)
+      .bind(i32::from(err))
+      .bind(next_attempt_at)
+      .bind(id)
+      .execute(&self.pool).await?;
+    Ok(())
+  }
+
+  /// all messages ever sent to a user, newest first.
+  pub async fn outbox_history(&self, open_id: &str) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let sql = select_outbox!("Prospect.notifyOutbox", "WHERE open_id = ? ORDER BY created_at DESC, id DESC");
+    let rows: Vec<OutboxRow> = sqlx::query_as(sql)
+      .bind(open_id)
+      .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().map(from_row).collect())
+  }
+}
@@ -0,0 +1,87 @@
+//! password hashing of web users
+//!
+//! Passwords are hashed with Argon2id into PHC strings carrying their own salt and parameters.
+//! Hashes of older accounts are hex SHA3-256 over password || salt, with salt in its own column;
+//! they are still accepted and replaced on the next successful log in.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Argon2, Params};
+use crypto::digest::Digest;
+use subtle::ConstantTimeEq;
+
+/// result of checking password against stored hash
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Verified {
+  Match,
+  /// password matches, but the hash is legacy or of outdated parameters and should be replaced
+  Rehash,
+  NotMatch,
+}
+
+/// Hash password with Argon2id of default parameters and a random salt, as PHC string.
+/// Hashing takes tens of milliseconds, so it runs on blocking threads.
+pub(crate) async fn hash_password(passwd: String) -> String {
+  tokio::task::spawn_blocking(move || {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+      .hash_password(passwd.as_bytes(), &salt)
+      .expect("default argon2 parameters are valid")
+      .to_string()
+  }).await.expect("password hashing panicked")
+}
+
+/// Check password against hash stored with salt, in constant time.
+pub(crate) async fn verify_password(salt: Vec<u8>, hash: String, passwd: String) -> Verified {
+  tokio::task::spawn_blocking(move || verify_password_blocking(&salt, &hash, &passwd))
+    .await.expect("password verification panicked")
+}
+
+/// Argon2id hash of default parameters matching no password of any user.
+const DUMMY_HASH: &str =
+  "$argon2id$v=19$m=19456,t=2,p=1$o/e4MqYei9z3igJZLtR5Xw$MesiEbmVVrPtF+jd0ugVeLoaIQBLly4uvIj2cOnpgj8";
+
+/// Check password against a hash no user has, for log in of unknown user to take as long as
+/// a wrong password does.
+pub(crate) async fn verify_dummy(passwd: String) {
+  verify_password(Vec::new(), DUMMY_HASH.into(), passwd).await;
+}
+
+fn verify_password_blocking(salt: &[u8], hash: &str, passwd: &str) -> Verified {
+  if !hash.starts_with('$') {
+    let legacy = kdf_with_salt(salt, passwd);
+    return if bool::from(legacy.as_bytes().ct_eq(hash.as_bytes())) {
+      Verified::Rehash
+    } else {
+      Verified::NotMatch
+    };
+  }
+  let parsed = match PasswordHash::new(hash) {
+    Ok(parsed) => parsed,
+    Err(_) => return Verified::NotMatch,
+  };
+  if Argon2::default().verify_password(passwd.as_bytes(), &parsed).is_err() {
+    return Verified::NotMatch;
+  }
+  let current = parsed.algorithm == argon2::ARGON2ID_IDENT
+    && Params::try_from(&parsed).is_ok_and(|p| p == Params::default());
+  if current {
+    Verified::Match
+  } else {
+    Verified::Rehash
+  }
+}
+
+/// legacy hash of password with salt
+fn kdf_with_salt(salt: &[u8], passwd: &str) -> String {
+  let mut hasher = crypto::sha3::Sha3::sha3_256();
+  let b = passwd.as_bytes()
+    .iter()
+    .chain(
+      salt.iter()
+    )
+    .copied()
+    .collect::<Vec<u8>>();
+  hasher.input(b.as_slice());
+  hasher.result_str()
+}
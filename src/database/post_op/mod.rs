@@ -0,0 +1,110 @@
+//! post catalog sql api definitions
+
+use chrono::{DateTime, Utc};
+
+use crate::wechat::types::{PostCursor, PostRecord, PostStatus, WaterFallInfo, WaterFallItem};
+
+use super::ProspectSqlPool;
+
+/// title, img_source_link, asset_path, author, publish_date, status, university_id, department_id
+type PostRow = (String, String, String, String, DateTime<Utc>, u8, Option<u32>, Option<u32>);
+
+// impl for post catalog
+impl ProspectSqlPool {
+  /// add a post to catalog, posts already recorded with same asset path are kept as is.
+  pub async fn add_post(&self, post: &PostRecord) -> Result<(), sqlx::Error> {
+    let sql =
+      "INSERT IGNORE INTO Prospect.posts \
+       (title, img_source_link, asset_path, author, publish_date, status, university_id, department_id) \
+       VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+    sqlx::query(sql)
+      .bind(&post.title)
+      .bind(&post.img_source_link)
+      .bind(&post.asset_path)
+      .bind(&post.author)
+      .bind(post.publish_date)
+      .bind(u8::from(post.status))
+      .bind(post.university_id)
+      .bind(post.department_id)
+      .execute(&self.pool).await?;
+    Ok(())
+  }
+
+  pub async fn set_post_status(&self, asset_path: &str, status: PostStatus) -> Result<(), sqlx::Error> {
+    let r = sqlx::query("UPDATE Prospect.posts SET status = ? WHERE asset_path = ?")
+      .bind(u8::from(status))
+      .bind(asset_path)
+      .execute(&self.pool).await?;
+    match r.rows_affected() {
+      0 => Err(sqlx::Error::RowNotFound),
+      _ => Ok(()),
+    }
+  }
+
+  /// get a published post by its asset path.
+  pub async fn get_post(&self, asset_path: &str) -> Result<PostRecord, sqlx::Error> {
+    let sql =
+      "SELECT title, img_source_link, asset_path, author, publish_date, status, university_id, department_id \
+       FROM Prospect.posts \
+       WHERE asset_path = ? AND status = ?";
+    let row: PostRow = sqlx::query_as(sql)
+      .bind(asset_path)
+      .bind(u8::from(PostStatus::Published))
+      .fetch_one(&self.pool).await?;
+    Ok(PostRecord {
+      title: row.0,
+      img_source_link: row.1,
+      asset_path: row.2,
+      author: row.3,
+      publish_date: row.4,
+      status: row.5.into(),
+      university_id: row.6,
+      department_id: row.7,
+    })
+  }
+
+  /// get a page of published posts after cursor, newest first,
+  /// and the cursor of next page if there are more posts.
+  pub async fn get_posts(
+    &self,
+    info: &WaterFallInfo,
+    cursor: Option<PostCursor>,
+  ) -> Result<(Vec<WaterFallItem>, Option<PostCursor>), sqlx::Error> {
+    let sql =
+      "SELECT id, publish_date, img_source_link, title, asset_path \
+       FROM Prospect.posts \
+       WHERE status = ? \
+       AND (? IS NULL OR university_id = ?) \
+       AND (? IS NULL OR department_id = ?) \
+       AND (? IS NULL OR publish_date < ? OR (publish_date = ? AND id < ?)) \
+       ORDER BY publish_date DESC, id DESC \
+       LIMIT ?";
+    let page_size = info.page_size();
+    let cursor_date = cursor.map(|c| c.publish_date);
+    let cursor_id = cursor.map(|c| c.id);
+    // fetch one more row to know if there is a next page
+    let mut rows: Vec<(u32, DateTime<Utc>, String, String, String)> = sqlx::query_as(sql)
+      .bind(u8::from(PostStatus::Published))
+      .bind(info.university_id)
+      .bind(info.university_id)
+      .bind(info.department_id)
+      .bind(info.department_id)
+      .bind(cursor_date)
+      .bind(cursor_date)
+      .bind(cursor_date)
+      .bind(cursor_id)
+      .bind(page_size + 1)
+      .fetch_all(&self.pool).await?;
+    let next_cursor = if rows.len() > page_size as usize {
+      rows.truncate(page_size as usize);
+      rows.last().map(|(id, publish_date, ..)| PostCursor { publish_date: *publish_date, id: *id })
+    } else {
+      None
+    };
+    let items = rows
+      .into_iter()
+      .map(|(_, _, img_source_link, title, post_id)| WaterFallItem::new(img_source_link, title, post_id))
+      .collect();
+    Ok((items, next_cursor))
+  }
+}
@@ -0,0 +1,897 @@
+//! SQLite store, for development and single-node deployments
+//!
+//! Schema mirrors the MySQL one and shares its versions, in its own dialect.
+//! The pool keeps a single connection: writes are serialized without SQLITE_BUSY,
+//! and `sqlite::memory:` databases live as long as the store.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{query, Pool, Sqlite, SqliteConnection};
+
+use crate::types::{hash_token, LogInInfo, SignUpInfo};
+use crate::wechat::types::{
+  AccessToken, Error, OutboxMessage, PostCursor, PostRecord, PostStatus, SessionRecord, SubscribeDetail, SubscribeInfo,
+  TokenRotation, WaterFallInfo, WaterFallItem,
+};
+
+use super::migration::{self, MigrateErr, MigrationFuture, SCHEMA_VERSIONS};
+use super::outbox_op::{from_row, select_outbox, OutboxRow};
+use super::store::ProspectStore;
+use super::wechat_op::TokenRefresh;
+use super::password::{self, Verified};
+use super::{LogInErr, SignUpErr};
+
+const INITIAL_SCHEMA: &[&str] = &[
+  // open_id --- access_token --- expired_time map
+  "CREATE TABLE IF NOT EXISTS tokenMap (\
+   open_id TEXT NOT NULL PRIMARY KEY ,\
+   access_token TEXT NOT NULL ,\
+   expired_time DATETIME NOT NULL\
+   )",
+  // mini-program access token, a single row
+  "CREATE TABLE IF NOT EXISTS wechatToken (\
+   id INTEGER NOT NULL PRIMARY KEY ,\
+   access_token TEXT NOT NULL ,\
+   expired_time DATETIME NOT NULL\
+   )",
+  "INSERT OR IGNORE INTO wechatToken (id, access_token, expired_time) VALUES (1, '', '1970-01-01T00:00:01+00:00')",
+  // university_id --- university_name map
+  "CREATE TABLE IF NOT EXISTS universities (\
+   id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT ,\
+   uni_name TEXT NOT NULL UNIQUE ,\
+   name TEXT NOT NULL\
+   )",
+  // (university_id, department_id) --- department_name map
+  "CREATE TABLE IF NOT EXISTS departments (\
+   university_id INTEGER NOT NULL REFERENCES universities (id) ON DELETE CASCADE ,\
+   id INTEGER NOT NULL ,\
+   uni_name TEXT NOT NULL UNIQUE ,\
+   name TEXT NOT NULL ,\
+   PRIMARY KEY (university_id, id)\
+   )",
+  // open_id --- department subscribed
+  "CREATE TABLE IF NOT EXISTS subscriptions (\
+   open_id TEXT NOT NULL ,\
+   university_id INTEGER NOT NULL ,\
+   department_id INTEGER NOT NULL ,\
+   PRIMARY KEY (open_id, university_id, department_id) ,\
+   FOREIGN KEY (university_id, department_id) REFERENCES departments (university_id, id) ON DELETE CASCADE\
+   )",
+  "CREATE INDEX IF NOT EXISTS subscriptions_department ON subscriptions (university_id, department_id)",
+  // post catalog shown in waterfall
+  "CREATE TABLE IF NOT EXISTS posts (\
+   id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT ,\
+   title TEXT NOT NULL ,\
+   img_source_link TEXT NOT NULL ,\
+   asset_path TEXT NOT NULL UNIQUE ,\
+   author TEXT NOT NULL ,\
+   publish_date DATETIME NOT NULL ,\
+   status INTEGER NOT NULL ,\
+   university_id INTEGER NULL ,\
+   department_id INTEGER NULL\
+   )",
+  "CREATE INDEX IF NOT EXISTS posts_status ON posts (status, publish_date)",
+  "CREATE INDEX IF NOT EXISTS posts_department ON posts (university_id, department_id)",
+  // subscription messages to send and sent
+  "CREATE TABLE IF NOT EXISTS notifyOutbox (\
+   id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT ,\
+   open_id TEXT NOT NULL ,\
+   university_id INTEGER NOT NULL ,\
+   department_id INTEGER NOT NULL ,\
+   template TEXT NOT NULL ,\
+   payload TEXT NOT NULL ,\
+   attempts INTEGER NOT NULL ,\
+   last_err_code INTEGER NULL ,\
+   created_at DATETIME NOT NULL ,\
+   next_attempt_at DATETIME NOT NULL ,\
+   delivered_at DATETIME NULL\
+   )",
+  "CREATE INDEX IF NOT EXISTS notifyOutbox_due ON notifyOutbox (delivered_at, next_attempt_at)",
+  "CREATE INDEX IF NOT EXISTS notifyOutbox_open_id ON notifyOutbox (open_id)",
+];
+
+const USER_AUTH: &[&str] = &[
+  // web users signed up with username and password
+  "CREATE TABLE IF NOT EXISTS UserAuth (\
+   user_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT ,\
+   username TEXT NOT NULL UNIQUE ,\
+   salt BLOB NOT NULL ,\
+   hash TEXT NOT NULL\
+   )",
+];
+
+const WEB_SESSION: &[&str] = &[
+  // access_token --- user_id --- expired_time of web users logged in
+  "CREATE TABLE IF NOT EXISTS webSession (\
+   access_token TEXT NOT NULL PRIMARY KEY ,\
+   user_id INTEGER NOT NULL REFERENCES UserAuth (user_id) ON DELETE CASCADE ,\
+   expired_time DATETIME NOT NULL\
+   )",
+  "CREATE INDEX IF NOT EXISTS webSession_user_id ON webSession (user_id)",
+];
+
+const HASHED_TOKENS: &[&str] = &[
+  // tokens issued before were recorded in plaintext and derived from open_id and time,
+  // drop them all so that users log in again through code2session
+  "DELETE FROM tokenMap",
+];
+
+const PER_SESSION_TOKENS: &[&str] = &[
+  // a row for each session, so that logging in on another device does not end the first one
+  "CREATE TABLE IF NOT EXISTS wechatSession (\
+   id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT ,\
+   open_id TEXT NOT NULL ,\
+   token_hash TEXT NOT NULL UNIQUE ,\
+   device TEXT NOT NULL ,\
+   created_at DATETIME NOT NULL ,\
+   last_seen_at DATETIME NOT NULL ,\
+   expired_time DATETIME NOT NULL\
+   )",
+  "CREATE INDEX IF NOT EXISTS wechatSession_open_id ON wechatSession (open_id)",
+  "INSERT OR IGNORE INTO wechatSession (open_id, token_hash, device, created_at, last_seen_at, expired_time) \
+   SELECT open_id, access_token, '', strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'), \
+   strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'), expired_time FROM tokenMap",
+  "DROP TABLE tokenMap",
+];
+
+const ROTATION_GRACE: &[&str] = &[
+  // token replaced on rotation and until when it is still accepted
+  "ALTER TABLE wechatSession ADD COLUMN prev_token_hash TEXT",
+  "ALTER TABLE wechatSession ADD COLUMN prev_expired_time DATETIME",
+];
+
+const HASHED_WEB_SESSIONS: &[&str] = &[
+  // sessions recorded before hold plaintext tokens, which look the same as hashes,
+  // drop them all so that web users log in again
+  "DELETE FROM webSession",
+  "ALTER TABLE webSession RENAME COLUMN access_token TO token_hash",
+];
+
+/// Add columns of the token replaced on rotation and until when it is still accepted,
+/// unless they are there already.
+fn rotation_grace(conn: &mut SqliteConnection) -> MigrationFuture<'_> {
+  Box::pin(async move {
+    if !column_exists(conn, "wechatSession", "prev_token_hash").await? {
+      for sql in ROTATION_GRACE {
+        query(sql).execute(&mut *conn).await?;
+      }
+    }
+    Ok(())
+  })
+}
+
+/// Keep only hash of web session tokens, unless the column was renamed already.
+fn hashed_web_sessions(conn: &mut SqliteConnection) -> MigrationFuture<'_> {
+  Box::pin(async move {
+    if column_exists(conn, "webSession", "access_token").await? {
+      for sql in HASHED_WEB_SESSIONS {
+        query(sql).execute(&mut *conn).await?;
+      }
+    }
+    Ok(())
+  })
+}
+
+/// if table has column.
+async fn column_exists(conn: &mut SqliteConnection, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+  let (count, ): (i64, ) = sqlx::query_as("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+    .bind(table)
+    .bind(column)
+    .fetch_one(&mut *conn).await?;
+  Ok(count > 0)
+}
+
+enum Up {
+  /// statements executed in order
+  Sql(&'static [&'static str]),
+  Code(fn(&mut SqliteConnection) -> MigrationFuture<'_>),
+}
+
+/// how to apply each of SCHEMA_VERSIONS, in the same order
+const MIGRATIONS: &[Up] = &[
+  Up::Sql(INITIAL_SCHEMA),
+  // there never were legacy tables in SQLite
+  Up::Sql(&[]),
+  Up::Sql(USER_AUTH),
+  Up::Sql(WEB_SESSION),
+  Up::Sql(HASHED_TOKENS),
+  Up::Sql(PER_SESSION_TOKENS),
+  Up::Code(rotation_grace),
+  Up::Code(hashed_web_sessions),
+];
+
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSIONS.len());
+
+/// id, device, created_at, last_seen_at, expired_time of a wechatSession row
+type SessionRow = (i64, String, DateTime<Utc>, DateTime<Utc>, DateTime<Utc>);
+
+/// id, token_hash, expired_time, prev_expired_time of a wechatSession row
+type TokenRow = (i64, String, DateTime<Utc>, Option<DateTime<Utc>>);
+
+/// title, img_source_link, asset_path, author, publish_date, status, university_id, department_id
+type PostRow = (String, String, String, String, DateTime<Utc>, u8, Option<u32>, Option<u32>);
+
+#[derive(Debug)]
+pub struct ProspectSqlitePool {
+  pool: Pool<Sqlite>,
+  /// held while refreshing mini-program access token, so that only one refresh runs at a time
+  token_lock: tokio::sync::Mutex<()>,
+}
+
+impl ProspectSqlitePool {
+  /// Open database at url, e.g. `sqlite://prospect.db` or `sqlite::memory:`,
+  /// creating it if missing and applying pending migrations.
+  pub async fn open(url: &str) -> Result<ProspectSqlitePool, MigrateErr> {
+    let store = Self::connect(url).await?;
+    let version = store.migrate().await?;
+    info!("sqlite database schema at version {}", version);
+    Ok(store)
+  }
+
+  /// open database at url, creating it if missing, without migrating.
+  pub async fn connect(url: &str) -> Result<ProspectSqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(url)?
+      .create_if_missing(true)
+      .foreign_keys(true);
+    let pool = SqlitePoolOptions::new()
+      .max_connections(1)
+      .idle_timeout(None)
+      .max_lifetime(None)
+      .connect_with(options).await?;
+    Ok(ProspectSqlitePool {
+      pool,
+      token_lock: tokio::sync::Mutex::new(()),
+    })
+  }
+
+  /// schema version of database, 0 if it was never migrated.
+  pub async fn current_version(&self) -> Result<u32, sqlx::Error> {
+    let (count, ): (i64, ) =
+      sqlx::query_as("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schemaVersion'")
+        .fetch_one(&self.pool).await?;
+    if count == 0 {
+      return Ok(0);
+    }
+    let (version, ): (Option<u32>, ) = sqlx::query_as("SELECT MAX(version) FROM schemaVersion")
+      .fetch_one(&self.pool).await?;
+    Ok(version.unwrap_or(0))
+  }
+
+  /// record session of token issued to user by hash of token, dropping expired sessions of the user.
+  async fn record_web_session(&self, user_id: u32, token: &crate::types::AccessToken) -> Result<(), sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    query("DELETE FROM webSession WHERE user_id = ? AND expired_time <= ?")
+      .bind(user_id)
+      .bind(Utc::now())
+      .execute(&mut tx).await?;
+    query("INSERT INTO webSession (token_hash, user_id, expired_time) VALUES (?, ?, ?)")
+      .bind(token.hash())
+      .bind(user_id)
+      .bind(token.expired)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+  }
+
+  /// latest schema version known by this binary.
+  pub fn latest_version() -> u32 {
+    migration::latest_version()
+  }
+
+  async fn migrate(&self) -> Result<u32, MigrateErr> {
+    query("CREATE TABLE IF NOT EXISTS schemaVersion (\
+           version INTEGER NOT NULL PRIMARY KEY ,\
+           description TEXT NOT NULL ,\
+           applied_at DATETIME NOT NULL\
+           )")
+      .execute(&self.pool).await?;
+    let current = self.current_version().await?;
+    if current > Self::latest_version() {
+      return Err(MigrateErr::DatabaseAhead { database: current, binary: Self::latest_version() });
+    }
+    let mut version = current;
+    // DDL is transactional in SQLite, each migration is applied entirely or not at all
+    for (&(v, description), up) in SCHEMA_VERSIONS.iter().zip(MIGRATIONS).filter(|((v, _), _)| *v > current) {
+      info!("apply migration {}: {}", v, description);
+      let mut tx = self.pool.begin().await?;
+      match *up {
+        Up::Sql(statements) => for sql in statements {
+          query(sql).execute(&mut tx).await?;
+        }
+        Up::Code(up) => up(&mut tx).await?,
+      }
+      query("INSERT INTO schemaVersion (version, description, applied_at) VALUES (?, ?, ?)")
+        .bind(v)
+        .bind(description)
+        .bind(Utc::now())
+        .execute(&mut tx).await?;
+      tx.commit().await?;
+      version = v;
+    }
+    Ok(version)
+  }
+}
+
+#[async_trait]
+impl ProspectStore for ProspectSqlitePool {
+  async fn session_id(&self, open_id: &str, token: AccessToken) -> Result<Option<u64>, sqlx::Error> {
+    let now = Utc::now();
+    let hash = token.hash();
+    let sql =
+      "SELECT id FROM wechatSession \
+       WHERE open_id = ? AND (token_hash = ? AND expired_time > ? OR prev_token_hash = ? AND prev_expired_time > ?)";
+    let row: Option<(i64, )> = sqlx::query_as(sql)
+      .bind(open_id)
+      .bind(&hash)
+      .bind(now)
+      .bind(&hash)
+      .bind(now)
+      .fetch_optional(&self.pool).await?;
+    if let Some((id, )) = row {
+      query("UPDATE wechatSession SET last_seen_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool).await?;
+    }
+    Ok(row.map(|(id, )| id as u64))
+  }
+
+  async fn record_token(&self, open_id: &str, token: AccessToken, device: &str) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = self.pool.begin().await?;
+    query("DELETE FROM wechatSession WHERE open_id = ? AND expired_time <= ?")
+      .bind(open_id)
+      .bind(now)
+      .execute(&mut tx).await?;
+    let sql =
+      "INSERT INTO wechatSession (open_id, token_hash, device, created_at, last_seen_at, expired_time) \
+       VALUES (?, ?, ?, ?, ?, ?)";
+    query(sql)
+      .bind(open_id)
+      .bind(token.hash())
+      .bind(device)
+      .bind(now)
+      .bind(now)
+      .bind(token.expired)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+  }
+
+  async fn valid_token_and_update(&self, token: AccessToken, open_id: &str, rotation: TokenRotation) -> Result<AccessToken, sqlx::Error> {
+    let now = Utc::now();
+    let hash = token.hash();
+    let mut tx = self.pool.begin().await?;
+    let sql =
+      "SELECT id, token_hash, expired_time, prev_expired_time FROM wechatSession \
+       WHERE open_id = ? AND (token_hash = ? AND expired_time > ? OR prev_token_hash = ? AND prev_expired_time > ?)";
+    let (id, current, expired, prev_expired): TokenRow = sqlx::query_as(sql)
+      .bind(open_id)
+      .bind(&hash)
+      .bind(now)
+      .bind(&hash)
+      .bind(now)
+      .fetch_one(&mut tx).await?;
+    let kept = if current != hash {
+      // replaced by a request sent in parallel
+      Some(AccessToken { token: token.token, expired: prev_expired.unwrap_or(now) })
+    } else if !rotation.is_due(expired) {
+      Some(AccessToken { token: token.token, expired })
+    } else {
+      None
+    };
+    if let Some(kept) = kept {
+      query("UPDATE wechatSession SET last_seen_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(&mut tx).await?;
+      tx.commit().await?;
+      return Ok(kept);
+    }
+    let new_token = AccessToken::new();
+    let sql =
+      "UPDATE wechatSession \
+       SET prev_token_hash = ?, prev_expired_time = ?, token_hash = ?, expired_time = ?, last_seen_at = ? \
+       WHERE id = ?";
+    query(sql)
+      .bind(&hash)
+      .bind(rotation.grace_expired(expired))
+      .bind(new_token.hash())
+      .bind(new_token.expired)
+      .bind(now)
+      .bind(id)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(new_token)
+  }
+
+  async fn get_sessions(&self, open_id: &str) -> Result<Vec<SessionRecord>, sqlx::Error> {
+    let sql =
+      "SELECT id, device, created_at, last_seen_at, expired_time FROM wechatSession \
+       WHERE open_id = ? AND expired_time > ? \
+       ORDER BY last_seen_at DESC, id DESC";
+    let rows: Vec<SessionRow> = sqlx::query_as(sql)
+      .bind(open_id)
+      .bind(Utc::now())
+      .fetch_all(&self.pool).await?;
+    let sessions = rows
+      .into_iter()
+      .map(|(id, device, created_at, last_seen_at, expired)| SessionRecord {
+        id: id as u64,
+        device,
+        created_at,
+        last_seen_at,
+        expired,
+      })
+      .collect();
+    Ok(sessions)
+  }
+
+  async fn revoke_session(&self, open_id: &str, session_id: u64) -> Result<(), sqlx::Error> {
+    let r = query("DELETE FROM wechatSession WHERE id = ? AND open_id = ?")
+      .bind(session_id as i64)
+      .bind(open_id)
+      .execute(&self.pool).await?;
+    match r.rows_affected() {
+      0 => Err(sqlx::Error::RowNotFound),
+      _ => Ok(()),
+    }
+  }
+
+  async fn revoke_sessions(&self, open_id: &str) -> Result<u64, sqlx::Error> {
+    let r = query("DELETE FROM wechatSession WHERE open_id = ?")
+      .bind(open_id)
+      .execute(&self.pool).await?;
+    Ok(r.rows_affected())
+  }
+
+  async fn shared_wechat_token(&self, refresh_ahead: Duration, refresh: TokenRefresh<'_>) -> Result<(String, DateTime<Utc>), Error> {
+    let _guard = self.token_lock.lock().await;
+    let current: (String, DateTime<Utc>) =
+      sqlx::query_as("SELECT access_token, expired_time FROM wechatToken WHERE id = 1")
+        .fetch_one(&self.pool).await
+        .map_err(|_| Error::DatabaseErr)?;
+    if !current.0.is_empty() && current.1 - Utc::now() > refresh_ahead {
+      return Ok(current);
+    }
+    match refresh.await {
+      Ok((access_token, expired_time)) => {
+        query("UPDATE wechatToken SET access_token = ?, expired_time = ? WHERE id = 1")
+          .bind(&access_token)
+          .bind(expired_time)
+          .execute(&self.pool).await
+          .map_err(|_| Error::DatabaseErr)?;
+        Ok((access_token, expired_time))
+      }
+      // refreshing ahead failed, current token still works for now
+      Err(e) if !current.0.is_empty() && current.1 > Utc::now() => {
+        warn!("refresh shared access token failed: {:?}, keep using current one", e);
+        Ok(current)
+      }
+      Err(e) => Err(e),
+    }
+  }
+
+  async fn add_university(&self, uni_name: &str, name: &str) -> Result<u32, sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    query("INSERT OR IGNORE INTO universities (uni_name, name) VALUES (?, ?)")
+      .bind(uni_name)
+      .bind(name)
+      .execute(&mut tx).await?;
+    let (id, ): (u32, ) = sqlx::query_as("SELECT id FROM universities WHERE uni_name = ?")
+      .bind(uni_name)
+      .fetch_one(&mut tx).await?;
+    tx.commit().await?;
+    Ok(id)
+  }
+
+  async fn remove_university(&self, university_id: u32) -> Result<u64, sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    query("SELECT id FROM universities WHERE id = ?")
+      .bind(university_id)
+      .fetch_one(&mut tx).await?;
+    let subscriptions = query("DELETE FROM subscriptions WHERE university_id = ?")
+      .bind(university_id)
+      .execute(&mut tx).await?.rows_affected();
+    query("DELETE FROM notifyOutbox WHERE university_id = ? AND delivered_at IS NULL")
+      .bind(university_id)
+      .execute(&mut tx).await?;
+    query("UPDATE posts SET university_id = NULL, department_id = NULL WHERE university_id = ?")
+      .bind(university_id)
+      .execute(&mut tx).await?;
+    query("DELETE FROM departments WHERE university_id = ?")
+      .bind(university_id)
+      .execute(&mut tx).await?;
+    query("DELETE FROM universities WHERE id = ?")
+      .bind(university_id)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(subscriptions)
+  }
+
+  async fn add_department(&self, university_id: u32, uni_name: &str, name: &str) -> Result<u32, sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    query("SELECT id FROM universities WHERE id = ?")
+      .bind(university_id)
+      .fetch_one(&mut tx).await?;
+    let existing: Option<(u32, )> =
+      sqlx::query_as("SELECT id FROM departments WHERE university_id = ? AND uni_name = ?")
+        .bind(university_id)
+        .bind(uni_name)
+        .fetch_optional(&mut tx).await?;
+    let department_id = match existing {
+      Some((id, )) => id,
+      None => {
+        let (id, ): (u32, ) =
+          sqlx::query_as("SELECT COALESCE(MAX(id), 0) + 1 FROM departments WHERE university_id = ?")
+            .bind(university_id)
+            .fetch_one(&mut tx).await?;
+        query("INSERT INTO departments (university_id, id, uni_name, name) VALUES (?, ?, ?, ?)")
+          .bind(university_id)
+          .bind(id)
+          .bind(uni_name)
+          .bind(name)
+          .execute(&mut tx).await?;
+        id
+      }
+    };
+    tx.commit().await?;
+    Ok(department_id)
+  }
+
+  async fn remove_department(&self, university_id: u32, department_id: u32) -> Result<u64, sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    query("SELECT id FROM departments WHERE university_id = ? AND id = ?")
+      .bind(university_id)
+      .bind(department_id)
+      .fetch_one(&mut tx).await?;
+    let subscriptions = query("DELETE FROM subscriptions WHERE university_id = ? AND department_id = ?")
+      .bind(university_id)
+      .bind(department_id)
+      .execute(&mut tx).await?.rows_affected();
+    query("DELETE FROM notifyOutbox \
+           WHERE university_id = ? AND department_id = ? AND delivered_at IS NULL")
+      .bind(university_id)
+      .bind(department_id)
+      .execute(&mut tx).await?;
+    query("UPDATE posts SET university_id = NULL, department_id = NULL WHERE university_id = ? AND department_id = ?")
+      .bind(university_id)
+      .bind(department_id)
+      .execute(&mut tx).await?;
+    query("DELETE FROM departments WHERE university_id = ? AND id = ?")
+      .bind(university_id)
+      .bind(department_id)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(subscriptions)
+  }
+
+  async fn get_universities(&self) -> Result<HashMap<u32, String>, sqlx::Error> {
+    let rows: Vec<(u32, String)> = sqlx::query_as("SELECT id, name FROM universities")
+      .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().collect())
+  }
+
+  async fn get_departments(&self, university_id: u32) -> Result<HashMap<u32, String>, sqlx::Error> {
+    // unknown university is reported as RowNotFound
+    query("SELECT id FROM universities WHERE id = ?")
+      .bind(university_id)
+      .fetch_one(&self.pool).await?;
+    let rows: Vec<(u32, String)> = sqlx::query_as("SELECT id, name FROM departments WHERE university_id = ?")
+      .bind(university_id)
+      .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().collect())
+  }
+
+  async fn get_university_name(&self, university_id: u32) -> Result<String, sqlx::Error> {
+    let (name, ): (String, ) = sqlx::query_as("SELECT name FROM universities WHERE id = ?")
+      .bind(university_id)
+      .fetch_one(&self.pool).await?;
+    Ok(name)
+  }
+
+  async fn get_department_name(&self, university_id: u32, department_id: u32) -> Result<String, sqlx::Error> {
+    let (name, ): (String, ) = sqlx::query_as("SELECT name FROM departments WHERE university_id = ? AND id = ?")
+      .bind(university_id)
+      .bind(department_id)
+      .fetch_one(&self.pool).await?;
+    Ok(name)
+  }
+
+  async fn subscribe_user(&self, info: SubscribeInfo) -> Result<(), sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    for each in info.info {
+      let SubscribeDetail {
+        school_code: university_id,
+        department_code: department_id,
+        oper
+      } = each;
+      // make sure department exists
+      query("SELECT id FROM departments WHERE university_id = ? AND id = ?")
+        .bind(university_id)
+        .bind(department_id)
+        .fetch_one(&mut tx).await?;
+      let sql = if oper == 0 {
+        "INSERT OR IGNORE INTO subscriptions (open_id, university_id, department_id) VALUES (?, ?, ?)"
+      } else {
+        "DELETE FROM subscriptions WHERE open_id = ? AND university_id = ? AND department_id = ?"
+      };
+      query(sql)
+        .bind(&info.open_id)
+        .bind(university_id)
+        .bind(department_id)
+        .execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+  }
+
+  async fn get_subscriptions(&self, open_id: &str) -> Result<HashMap<u32, Vec<u32>>, sqlx::Error> {
+    let rows: Vec<(u32, u32)> = sqlx::query_as("SELECT university_id, department_id FROM subscriptions WHERE open_id = ?")
+      .bind(open_id)
+      .fetch_all(&self.pool).await?;
+    let map = rows
+      .into_iter()
+      .fold(HashMap::new(), |mut map, (uni_id, dep_id)| {
+        map.entry(uni_id).or_insert_with(Vec::new).push(dep_id);
+        map
+      });
+    Ok(map)
+  }
+
+  async fn get_users(&self, university_id: u32, department_id: u32) -> Result<Vec<String>, sqlx::Error> {
+    let rows: Vec<(String, )> =
+      sqlx::query_as("SELECT open_id FROM subscriptions WHERE university_id = ? AND department_id = ?")
+        .bind(university_id)
+        .bind(department_id)
+        .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().map(|(open_id, )| open_id).collect())
+  }
+
+  async fn add_post(&self, post: &PostRecord) -> Result<(), sqlx::Error> {
+    let sql =
+      "INSERT OR IGNORE INTO posts \
+       (title, img_source_link, asset_path, author, publish_date, status, university_id, department_id) \
+       VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+    query(sql)
+      .bind(&post.title)
+      .bind(&post.img_source_link)
+      .bind(&post.asset_path)
+      .bind(&post.author)
+      .bind(post.publish_date)
+      .bind(u8::from(post.status))
+      .bind(post.university_id)
+      .bind(post.department_id)
+      .execute(&self.pool).await?;
+    Ok(())
+  }
+
+  async fn set_post_status(&self, asset_path: &str, status: PostStatus) -> Result<(), sqlx::Error> {
+    let r = query("UPDATE posts SET status = ? WHERE asset_path = ?")
+      .bind(u8::from(status))
+      .bind(asset_path)
+      .execute(&self.pool).await?;
+    match r.rows_affected() {
+      0 => Err(sqlx::Error::RowNotFound),
+      _ => Ok(()),
+    }
+  }
+
+  async fn get_post(&self, asset_path: &str) -> Result<PostRecord, sqlx::Error> {
+    let sql =
+      "SELECT title, img_source_link, asset_path, author, publish_date, status, university_id, department_id \
+       FROM posts WHERE asset_path = ? AND status = ?";
+    let row: PostRow = sqlx::query_as(sql)
+      .bind(asset_path)
+      .bind(u8::from(PostStatus::Published))
+      .fetch_one(&self.pool).await?;
+    Ok(PostRecord {
+      title: row.0,
+      img_source_link: row.1,
+      asset_path: row.2,
+      author: row.3,
+      publish_date: row.4,
+      status: row.5.into(),
+      university_id: row.6,
+      department_id: row.7,
+    })
+  }
+
+  async fn get_posts(
+    &self,
+    info: &WaterFallInfo,
+    cursor: Option<PostCursor>,
+  ) -> Result<(Vec<WaterFallItem>, Option<PostCursor>), sqlx::Error> {
+    let sql =
+      "SELECT id, publish_date, img_source_link, title, asset_path \
+       FROM posts \
+       WHERE status = ? \
+       AND (? IS NULL OR university_id = ?) \
+       AND (? IS NULL OR department_id = ?) \
+       AND (? IS NULL OR publish_date < ? OR (publish_date = ? AND id < ?)) \
+       ORDER BY publish_date DESC, id DESC \
+       LIMIT ?";
+    let page_size = info.page_size();
+    let cursor_date = cursor.map(|c| c.publish_date);
+    let cursor_id = cursor.map(|c| c.id);
+    // fetch one more row to know if there is a next page
+    let mut rows: Vec<(u32, DateTime<Utc>, String, String, String)> = sqlx::query_as(sql)
+      .bind(u8::from(PostStatus::Published))
+      .bind(info.university_id)
+      .bind(info.university_id)
+      .bind(info.department_id)
+      .bind(info.department_id)
+      .bind(cursor_date)
+      .bind(cursor_date)
+      .bind(cursor_date)
+      .bind(cursor_id)
+      .bind(page_size + 1)
+      .fetch_all(&self.pool).await?;
+    let next_cursor = if rows.len() > page_size as usize {
+      rows.truncate(page_size as usize);
+      rows.last().map(|(id, publish_date, ..)| PostCursor { publish_date: *publish_date, id: *id })
+    } else {
+      None
+    };
+    let items = rows
+      .into_iter()
+      .map(|(_, _, img_source_link, title, post_id)| WaterFallItem::new(img_source_link, title, post_id))
+      .collect();
+    Ok((items, next_cursor))
+  }
+
+  async fn outbox_enqueue(&self, messages: Vec<OutboxMessage>) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let sql =
+      "INSERT INTO notifyOutbox \
+       (open_id, university_id, department_id, template, payload, attempts, created_at, next_attempt_at) \
+       VALUES (?, ?, ?, ?, ?, 0, ?, ?)";
+    let mut tx = self.pool.begin().await?;
+    let mut recorded = Vec::with_capacity(messages.len());
+    for mut message in messages {
+      let r = query(sql)
+        .bind(&message.open_id)
+        .bind(message.university_id)
+        .bind(message.department_id)
+        .bind(&message.template)
+        .bind(&message.payload)
+        .bind(message.created_at)
+        .bind(message.next_attempt_at)
+        .execute(&mut tx).await?;
+      message.id = r.last_insert_rowid() as u64;
+      recorded.push(message);
+    }
+    tx.commit().await?;
+    Ok(recorded)
+  }
+
+  async fn outbox_due(&self, max_attempts: u32, limit: u32) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let sql = select_outbox!(
+      "notifyOutbox",
+      "WHERE delivered_at IS NULL AND attempts < ? AND next_attempt_at <= ? \
+       ORDER BY next_attempt_at \
+       LIMIT ?"
+    );
+    let rows: Vec<OutboxRow<i64>> = sqlx::query_as(sql)
+      .bind(max_attempts)
+      .bind(Utc::now())
+      .bind(limit)
+      .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().map(from_row).collect())
+  }
+
+  async fn outbox_claim(&self, id: u64, lease_until: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    let sql =
+      "UPDATE notifyOutbox SET next_attempt_at = ? \
+       WHERE id = ? AND delivered_at IS NULL AND next_attempt_at <= ?";
+    let r = query(sql)
+      .bind(lease_until)
+      .bind(id as i64)
+      .bind(Utc::now())
+      .execute(&self.pool).await?;
+    Ok(r.rows_affected() == 1)
+  }
+
+  async fn outbox_mark_delivered(&self, id: u64) -> Result<(), sqlx::Error> {
+    query("UPDATE notifyOutbox SET attempts = attempts + 1, last_err_code = NULL, delivered_at = ? WHERE id = ?")
+      .bind(Utc::now())
+      .bind(id as i64)
+      .execute(&self.pool).await?;
+    Ok(())
+  }
+
+  async fn outbox_mark_failed(&self, id: u64, err: Error, attempts: u32, next_attempt_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    query("UPDATE notifyOutbox SET attempts = ?, last_err_code = ?, next_attempt_at = ? WHERE id = ?")
+      .bind(attempts)
+      .bind(i32::from(err))
+      .bind(next_attempt_at)
+      .bind(id as i64)
+      .execute(&self.pool).await?;
+    Ok(())
+  }
+
+  async fn outbox_history(&self, open_id: &str) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let sql = select_outbox!("notifyOutbox", "WHERE open_id = ? ORDER BY created_at DESC, id DESC");
+    let rows: Vec<OutboxRow<i64>> = sqlx::query_as(sql)
+      .bind(open_id)
+      .fetch_all(&self.pool).await?;
+    Ok(rows.into_iter().map(from_row).collect())
+  }
+
+  async fn sign_up(&self, info: SignUpInfo) -> Result<(), SignUpErr> {
+    let r: Result<(u32, ), _> = sqlx::query_as("SELECT user_id FROM UserAuth WHERE username = ?")
+      .bind(&info.username)
+      .fetch_one(&self.pool).await;
+    match r {
+      Ok(_) => Err(SignUpErr::UserExist),
+      Err(sqlx::Error::RowNotFound) => {
+        let hash = password::hash_password(info.password).await;
+        // salt is kept in argon2 hash
+        query("INSERT INTO UserAuth (username, salt, hash) VALUES (?, x'', ?)")
+          .bind(&info.username)
+          .bind(&hash)
+          .execute(&self.pool).await
+          .map_err(|_| SignUpErr::OtherErr)?;
+        Ok(())
+      }
+      Err(_) => Err(SignUpErr::OtherErr),
+    }
+  }
+
+  async fn log_in(&self, info: LogInInfo) -> Result<(u32, crate::types::AccessToken), LogInErr> {
+    let r: Result<(u32, Vec<u8>, String), _> =
+      sqlx::query_as("SELECT user_id, salt, hash FROM UserAuth WHERE username = ?")
+        .bind(&info.username)
+        .fetch_one(&self.pool).await;
+    match r {
+      Ok((user_id, salt, hash)) => {
+        let verified = password::verify_password(salt, hash, info.password.clone()).await;
+        if verified == Verified::NotMatch {
+          return Err(LogInErr::InvalidCredentials);
+        }
+        if verified == Verified::Rehash {
+          let hash = password::hash_password(info.password).await;
+          let r = query("UPDATE UserAuth SET salt = x'', hash = ? WHERE user_id = ?")
+            .bind(&hash)
+            .bind(user_id)
+            .execute(&self.pool).await;
+          match r {
+            Ok(_) => info!("password hash of user {} upgraded", user_id),
+            Err(e) => warn!("upgrade password hash of user {} failed: {:?}", user_id, e),
+          }
+        }
+        let token = crate::types::AccessToken::new();
+        self.record_web_session(user_id, &token).await.map_err(|_| LogInErr::OtherErr)?;
+        Ok((user_id, token))
+      }
+      Err(sqlx::Error::RowNotFound) => {
+        password::verify_dummy(info.password).await;
+        Err(LogInErr::InvalidCredentials)
+      }
+      Err(_) => Err(LogInErr::OtherErr),
+    }
+  }
+
+  async fn web_session_user(&self, token: &str) -> Result<u32, sqlx::Error> {
+    let (user_id, ): (u32, ) =
+      sqlx::query_as("SELECT user_id FROM webSession WHERE token_hash = ? AND expired_time > ?")
+        .bind(hash_token(token))
+        .bind(Utc::now())
+        .fetch_one(&self.pool).await?;
+    Ok(user_id)
+  }
+
+  async fn log_out(&self, token: &str) -> Result<(), sqlx::Error> {
+    let r = query("DELETE FROM webSession WHERE token_hash = ?")
+      .bind(hash_token(token))
+      .execute(&self.pool).await?;
+    match r.rows_affected() {
+      0 => Err(sqlx::Error::RowNotFound),
+      _ => Ok(()),
+    }
+  }
+}
@@ -0,0 +1,393 @@
+//! storage interface of backend, implemented by MySQL and SQLite pools and in-memory store
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+
+use crate::types::{LogInInfo, SignUpInfo};
+use crate::wechat::types::{
+  AccessToken, Error, OutboxMessage, PostCursor, PostFrontMatter, PostRecord, PostStatus, SessionRecord, SubscribeInfo,
+  TokenRotation, WaterFallInfo, WaterFallItem,
+};
+
+use super::wechat_op::TokenRefresh;
+use super::{LogInErr, ProspectSqlPool, SignUpErr};
+
+/// Storage used by handlers and background tasks.
+///
+/// Records not found are reported as `sqlx::Error::RowNotFound` by every backend.
+#[async_trait]
+pub trait ProspectStore: Debug + Send + Sync {
+  // sessions of mini-program users, one for each device logged in
+
+  /// id of unexpired session of user with token, or with its replaced token within grace,
+  /// marking it seen now.
+  async fn session_id(&self, open_id: &str, token: AccessToken) -> Result<Option<u64>, sqlx::Error>;
+
+  async fn is_valid_access_token(&self, open_id: &str, token: AccessToken) -> Result<bool, sqlx::Error> {
+    Ok(self.session_id(open_id, token).await?.is_some())
+  }
+
+  /// start a new session of user on device with token, sessions on other devices are kept.
+  async fn record_token(&self, open_id: &str, token: AccessToken, device: &str) -> Result<(), sqlx::Error>;
+
+  /// check token passed in if expired, then replace token of its session if due for rotation.
+  /// A token replaced within grace is still accepted and returned with its shortened expiry,
+  /// the token replacing it cannot be returned as only its hash is kept.
+  async fn valid_token_and_update(&self, token: AccessToken, open_id: &str, rotation: TokenRotation) -> Result<AccessToken, sqlx::Error>;
+
+  /// unexpired sessions of user, most recently seen first.
+  async fn get_sessions(&self, open_id: &str) -> Result<Vec<SessionRecord>, sqlx::Error>;
+
+  /// revoke a session of user, RowNotFound if user has no such session.
+  async fn revoke_session(&self, open_id: &str, session_id: u64) -> Result<(), sqlx::Error>;
+
+  /// revoke every session of user, return number of sessions revoked.
+  async fn revoke_sessions(&self, open_id: &str) -> Result<u64, sqlx::Error>;
+
+  /// Get mini-program access token shared by all server instances, running refresh if less than
+  /// refresh_ahead of its lifetime left. Only one refresh runs at a time.
+  async fn shared_wechat_token(&self, refresh_ahead: Duration, refresh: TokenRefresh<'_>) -> Result<(String, DateTime<Utc>), Error>;
+
+  // universities and departments
+
+  /// add a university if there is none with the same uni_name, return its id.
+  async fn add_university(&self, uni_name: &str, name: &str) -> Result<u32, sqlx::Error>;
+
+  /// remove a university with its departments, their subscriptions and undelivered notifications,
+  /// return number of subscriptions removed.
+  async fn remove_university(&self, university_id: u32) -> Result<u64, sqlx::Error>;
+
+  /// add a department to university if there is none with the same uni_name, return its id.
+  async fn add_department(&self, university_id: u32, uni_name: &str, name: &str) -> Result<u32, sqlx::Error>;
+
+  /// remove a department with its subscriptions and undelivered notifications,
+  /// return number of subscriptions removed.
+  async fn remove_department(&self, university_id: u32, department_id: u32) -> Result<u64, sqlx::Error>;
+
+  /// id --- name of all universities.
+  async fn get_universities(&self) -> Result<HashMap<u32, String>, sqlx::Error>;
+
+  /// id --- name of departments of a university.
+  async fn get_departments(&self, university_id: u32) -> Result<HashMap<u32, String>, sqlx::Error>;
+
+  async fn get_university_name(&self, university_id: u32) -> Result<String, sqlx::Error>;
+
+  async fn get_department_name(&self, university_id: u32, department_id: u32) -> Result<String, sqlx::Error>;
+
+  // subscriptions
+
+  /// subscribe (oper 0) or unsubscribe (other oper) user to departments.
+  async fn subscribe_user(&self, info: SubscribeInfo) -> Result<(), sqlx::Error>;
+
+  /// university id --- department ids subscribed by user.
+  async fn get_subscriptions(&self, open_id: &str) -> Result<HashMap<u32, Vec<u32>>, sqlx::Error>;
+
+  /// open_id of all subscribers of a department.
+  async fn get_users(&self, university_id: u32, department_id: u32) -> Result<Vec<String>, sqlx::Error>;
+
+  // posts
+
+  /// add a post to catalog, posts already recorded with same asset path are kept as is.
+  async fn add_post(&self, post: &PostRecord) -> Result<(), sqlx::Error>;
+
+  async fn set_post_status(&self, asset_path: &str, status: PostStatus) -> Result<(), sqlx::Error>;
+
+  /// get a published post by its asset path.
+  async fn get_post(&self, asset_path: &str) -> Result<PostRecord, sqlx::Error>;
+
+  /// get a page of published posts after cursor, newest first,
+  /// and the cursor of next page if there are more posts.
+  async fn get_posts(
+    &self,
+    info: &WaterFallInfo,
+    cursor: Option<PostCursor>,
+  ) -> Result<(Vec<WaterFallItem>, Option<PostCursor>), sqlx::Error>;
+
+  // notification outbox
+
+  /// record messages in outbox, return them with id assigned.
+  async fn outbox_enqueue(&self, messages: Vec<OutboxMessage>) -> Result<Vec<OutboxMessage>, sqlx::Error>;
+
+  /// undelivered messages due for another attempt, oldest first.
+  async fn outbox_due(&self, max_attempts: u32, limit: u32) -> Result<Vec<OutboxMessage>, sqlx::Error>;
+
+  /// take a due message for delivery until lease_until, return false if someone else took it.
+  async fn outbox_claim(&self, id: u64, lease_until: DateTime<Utc>) -> Result<bool, sqlx::Error>;
+
+  async fn outbox_mark_delivered(&self, id: u64) -> Result<(), sqlx::Error>;
+
+  /// record a failed attempt, message is retried at next_attempt_at,
+  /// or given up if attempts is set to max_attempts.
+  async fn outbox_mark_failed(&self, id: u64, err: Error, attempts: u32, next_attempt_at: DateTime<Utc>) -> Result<(), sqlx::Error>;
+
+  /// all messages ever sent to a user, newest first.
+  async fn outbox_history(&self, open_id: &str) -> Result<Vec<OutboxMessage>, sqlx::Error>;
+
+  // users of web
+
+  async fn sign_up(&self, info: SignUpInfo) -> Result<(), SignUpErr>;
+
+  /// check password of user and start a session, return user_id and token of the session.
+  async fn log_in(&self, info: LogInInfo) -> Result<(u32, crate::types::AccessToken), LogInErr>;
+
+  /// user_id of session with token, RowNotFound if there is none or it expired.
+  async fn web_session_user(&self, token: &str) -> Result<u32, sqlx::Error>;
+
+  /// revoke session with token, RowNotFound if there is none.
+  async fn log_out(&self, token: &str) -> Result<(), sqlx::Error>;
+
+  // assets
+
+  /// Register universities and departments under assets_path/paper and posts under assets_path/post.
+  /// Posts under post/{university}/{department} are tagged with that university and department if
+  /// it is under paper, folders without one are skipped.
+  async fn init_from_assets(&self, assets_path: String) -> Result<(), sqlx::Error> {
+    // init paper, keeping name_hash --- id of what is registered to tag posts with
+    let mut universities = HashMap::new();
+    let mut departments = HashMap::new();
+    for (name, _) in entries_of(&(assets_path.clone() + "/paper"), true) {
+      let nh = ProspectSqlPool::name_hash(&name);
+      let university_id = self.add_university(&nh, &name).await?;
+      universities.insert(nh, university_id);
+      for (depart_name, _) in entries_of(&(assets_path.clone() + "/paper/" + &name), true) {
+        let to_hash = name.clone() + &depart_name;
+        let nh = ProspectSqlPool::name_hash(&to_hash);
+        let department_id = self.add_department(university_id, &nh, &depart_name).await?;
+        departments.insert(nh, department_id);
+      }
+    }
+    // init post
+    let post_path = assets_path.clone() + "/post";
+    add_posts_in_dir(self, &post_path, "post", None, None).await?;
+    for (name, _) in entries_of(&post_path, true) {
+      let university_path = post_path.clone() + "/" + &name;
+      let Some(&university_id) = universities.get(&ProspectSqlPool::name_hash(&name)) else {
+        warn!("skip posts under {}, no university {} under paper", university_path, name);
+        continue;
+      };
+      let university_rel = "post/".to_string() + &name;
+      add_posts_in_dir(self, &university_path, &university_rel, Some(university_id), None).await?;
+      for (depart_name, _) in entries_of(&university_path, true) {
+        let department_path = university_path.clone() + "/" + &depart_name;
+        let Some(&department_id) = departments.get(&ProspectSqlPool::name_hash(&(name.clone() + &depart_name))) else {
+          warn!("skip posts under {}, no department {} of {} under paper", department_path, depart_name, name);
+          continue;
+        };
+        add_posts_in_dir(
+          self,
+          &department_path,
+          &(university_rel.clone() + "/" + &depart_name),
+          Some(university_id),
+          Some(department_id),
+        ).await?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// names and entries of directories, or files if not dirs, directly under dir.
+/// A missing or unreadable dir is treated as empty and names that are not UTF-8 are skipped, both logged.
+fn entries_of(dir: &str, dirs: bool) -> Vec<(String, std::fs::DirEntry)> {
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) => {
+      warn!("skip assets dir {}: {:?}", dir, e);
+      return Vec::new();
+    }
+  };
+  entries
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| entry.file_type().is_ok_and(|tp| if dirs { tp.is_dir() } else { tp.is_file() }))
+    .filter_map(|entry| match entry.file_name().into_string() {
+      Ok(name) => Some((name, entry)),
+      Err(name) => {
+        warn!("skip {:?} under {}, name is not utf-8", name, dir);
+        None
+      }
+    })
+    .collect()
+}
+
+/// record every markdown file directly under dir as a published post,
+/// rel is the path of dir relative to assets path.
+async fn add_posts_in_dir<S: ProspectStore + ?Sized>(
+  store: &S,
+  dir: &str,
+  rel: &str,
+  university_id: Option<u32>,
+  department_id: Option<u32>,
+) -> Result<(), sqlx::Error> {
+  for (name, file) in entries_of(dir, false) {
+    let path = file.path();
+    if path.extension().is_none_or(|ext| ext != "md") {
+      continue;
+    }
+    let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+    // use picture with same name as cover if there is one
+    let img_source_link = ["png", "jpg", "jpeg", "webp"]
+      .iter()
+      .map(|ext| format!("{}.{}", stem, ext))
+      .find(|img| std::path::Path::new(dir).join(img).is_file())
+      .map_or_else(String::new, |img| format!("{}/{}", rel, img));
+    // prefer metadata in front-matter, fall back to file name and modified time
+    let markdown = std::fs::read_to_string(&path).unwrap_or_default();
+    let (front_matter, _) = PostFrontMatter::parse(&markdown);
+    let publish_date = front_matter.publish_date().unwrap_or_else(|| {
+      file.metadata()
+        .and_then(|m| m.modified())
+        .map_or_else(|_| chrono::Utc::now(), |t| t.into())
+    });
+    let post = PostRecord {
+      title: front_matter.title.unwrap_or_else(|| stem.replace('_', " ")),
+      img_source_link,
+      asset_path: format!("{}/{}", rel, name),
+      author: front_matter.author.unwrap_or_default(),
+      publish_date,
+      status: PostStatus::Published,
+      university_id,
+      department_id,
+    };
+    store.add_post(&post).await?;
+  }
+  Ok(())
+}
+
+#[async_trait]
+impl ProspectStore for ProspectSqlPool {
+  async fn session_id(&self, open_id: &str, token: AccessToken) -> Result<Option<u64>, sqlx::Error> {
+    ProspectSqlPool::session_id(self, open_id, token).await
+  }
+
+  async fn record_token(&self, open_id: &str, token: AccessToken, device: &str) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::record_token(self, open_id, token, device).await
+  }
+
+  async fn valid_token_and_update(&self, token: AccessToken, open_id: &str, rotation: TokenRotation) -> Result<AccessToken, sqlx::Error> {
+    ProspectSqlPool::valid_token_and_update(self, token, open_id, rotation).await
+  }
+
+  async fn get_sessions(&self, open_id: &str) -> Result<Vec<SessionRecord>, sqlx::Error> {
+    ProspectSqlPool::get_sessions(self, open_id).await
+  }
+
+  async fn revoke_session(&self, open_id: &str, session_id: u64) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::revoke_session(self, open_id, session_id).await
+  }
+
+  async fn revoke_sessions(&self, open_id: &str) -> Result<u64, sqlx::Error> {
+    ProspectSqlPool::revoke_sessions(self, open_id).await
+  }
+
+  async fn shared_wechat_token(&self, refresh_ahead: Duration, refresh: TokenRefresh<'_>) -> Result<(String, DateTime<Utc>), Error> {
+    ProspectSqlPool::shared_wechat_token(self, refresh_ahead, refresh).await
+  }
+
+  async fn add_university(&self, uni_name: &str, name: &str) -> Result<u32, sqlx::Error> {
+    ProspectSqlPool::add_university(self, uni_name, name).await
+  }
+
+  async fn remove_university(&self, university_id: u32) -> Result<u64, sqlx::Error> {
+    ProspectSqlPool::remove_university(self, university_id).await
+  }
+
+  async fn add_department(&self, university_id: u32, uni_name: &str, name: &str) -> Result<u32, sqlx::Error> {
+    ProspectSqlPool::add_department(self, university_id, uni_name, name).await
+  }
+
+  async fn remove_department(&self, university_id: u32, department_id: u32) -> Result<u64, sqlx::Error> {
+    ProspectSqlPool::remove_department(self, university_id, department_id).await
+  }
+
+  async fn get_universities(&self) -> Result<HashMap<u32, String>, sqlx::Error> {
+    ProspectSqlPool::get_universities(self).await
+  }
+
+  async fn get_departments(&self, university_id: u32) -> Result<HashMap<u32, String>, sqlx::Error> {
+    ProspectSqlPool::get_departments(self, university_id).await
+  }
+
+  async fn get_university_name(&self, university_id: u32) -> Result<String, sqlx::Error> {
+    ProspectSqlPool::get_university_name(self, university_id).await
+  }
+
+  async fn get_department_name(&self, university_id: u32, department_id: u32) -> Result<String, sqlx::Error> {
+    ProspectSqlPool::get_department_name(self, university_id, department_id).await
+  }
+
+  async fn subscribe_user(&self, info: SubscribeInfo) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::subscribe_user(self, info).await
+  }
+
+  async fn get_subscriptions(&self, open_id: &str) -> Result<HashMap<u32, Vec<u32>>, sqlx::Error> {
+    ProspectSqlPool::get_subscriptions(self, open_id).await
+  }
+
+  async fn get_users(&self, university_id: u32, department_id: u32) -> Result<Vec<String>, sqlx::Error> {
+    ProspectSqlPool::get_users(self, university_id, department_id).await
+  }
+
+  async fn add_post(&self, post: &PostRecord) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::add_post(self, post).await
+  }
+
+  async fn set_post_status(&self, asset_path: &str, status: PostStatus) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::set_post_status(self, asset_path, status).await
+  }
+
+  async fn get_post(&self, asset_path: &str) -> Result<PostRecord, sqlx::Error> {
+    ProspectSqlPool::get_post(self, asset_path).await
+  }
+
+  async fn get_posts(
+    &self,
+    info: &WaterFallInfo,
+    cursor: Option<PostCursor>,
+  ) -> Result<(Vec<WaterFallItem>, Option<PostCursor>), sqlx::Error> {
+    ProspectSqlPool::get_posts(self, info, cursor).await
+  }
+
+  async fn outbox_enqueue(&self, messages: Vec<OutboxMessage>) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    ProspectSqlPool::outbox_enqueue(self, messages).await
+  }
+
+  async fn outbox_due(&self, max_attempts: u32, limit: u32) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    ProspectSqlPool::outbox_due(self, max_attempts, limit).await
+  }
+
+  async fn outbox_claim(&self, id: u64, lease_until: DateTime<Utc>) -> Result<bool, sqlx::Error> {
+    ProspectSqlPool::outbox_claim(self, id, lease_until).await
+  }
+
+  async fn outbox_mark_delivered(&self, id: u64) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::outbox_mark_delivered(self, id).await
+  }
+
+  async fn outbox_mark_failed(&self, id: u64, err: Error, attempts: u32, next_attempt_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::outbox_mark_failed(self, id, err, attempts, next_attempt_at).await
+  }
+
+  async fn outbox_history(&self, open_id: &str) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    ProspectSqlPool::outbox_history(self, open_id).await
+  }
+
+  async fn sign_up(&self, info: SignUpInfo) -> Result<(), SignUpErr> {
+    ProspectSqlPool::sign_up(self, info).await
+  }
+
+  async fn log_in(&self, info: LogInInfo) -> Result<(u32, crate::types::AccessToken), LogInErr> {
+    ProspectSqlPool::log_in(self, info).await
+  }
+
+  async fn web_session_user(&self, token: &str) -> Result<u32, sqlx::Error> {
+    ProspectSqlPool::web_session_user(self, token).await
+  }
+
+  async fn log_out(&self, token: &str) -> Result<(), sqlx::Error> {
+    ProspectSqlPool::log_out(self, token).await
+  }
+}
@@ -0,0 +1,47 @@
+//! web session sql api definitions
+
+use chrono::Utc;
+
+use crate::types::{hash_token, AccessToken};
+
+use super::ProspectSqlPool;
+
+// impl for web sessions
+impl ProspectSqlPool {
+  /// record session of token issued to user by hash of token, dropping expired sessions of the user.
+  pub async fn record_web_session(&self, user_id: u32, token: &AccessToken) -> Result<(), sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    sqlx::query("DELETE FROM Prospect.webSession WHERE user_id = ? AND expired_time <= ?")
+      .bind(user_id)
+      .bind(Utc::now())
+      .execute(&mut tx).await?;
+    sqlx::query("INSERT INTO Prospect.webSession (token_hash, user_id, expired_time) VALUES (?, ?, ?)")
+      .bind(token.hash())
+      .bind(user_id)
+      .bind(token.expired)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+  }
+
+  /// user_id of session with token, RowNotFound if there is none or it expired.
+  pub async fn web_session_user(&self, token: &str) -> Result<u32, sqlx::Error> {
+    let sql = "SELECT user_id FROM Prospect.webSession WHERE token_hash = ? AND expired_time > ?";
+    let (user_id, ): (u32, ) = sqlx::query_as(sql)
+      .bind(hash_token(token))
+      .bind(Utc::now())
+      .fetch_one(&self.pool).await?;
+    Ok(user_id)
+  }
+
+  /// revoke session with token, RowNotFound if there is none.
+  pub async fn log_out(&self, token: &str) -> Result<(), sqlx::Error> {
+    let r = sqlx::query("DELETE FROM Prospect.webSession WHERE token_hash = ?")
+      .bind(hash_token(token))
+      .execute(&self.pool).await?;
+    match r.rows_affected() {
+      0 => Err(sqlx::Error::RowNotFound),
+      _ => Ok(()),
+    }
+  }
+}
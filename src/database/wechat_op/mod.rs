@@ -1,155 +1,202 @@
-/// wechat sql api definitions
+//! wechat sql api definitions
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 
 use chrono::prelude::*;
-use log::{info, warn};
+use chrono::Duration;
+use log::warn;
 
-use crate::wechat::common::get_access_token;
-use crate::wechat::to_wechat_types::{SendMessage, SendMessageResult, SubscribeTemplate};
-use crate::wechat::types::{AccessToken, Context, Error, GetSubscribeInfo, SubscribeDetail, SubscribeInfo};
+use crate::wechat::types::{AccessToken, Error, SessionRecord, TokenRotation};
 
 use super::ProspectSqlPool;
 
-// impl for public wechat operation
+/// id, device, created_at, last_seen_at, expired_time of a wechatSession row
+type SessionRow = (u64, String, DateTime<Utc>, DateTime<Utc>, DateTime<Utc>);
+
+/// id, token_hash, expired_time, prev_expired_time of a wechatSession row
+type TokenRow = (u64, String, DateTime<Utc>, Option<DateTime<Utc>>);
+
+// impl for sessions of mini-program users
 impl ProspectSqlPool {
-  pub async fn is_valid_access_token(&self, open_id: &str, token: AccessToken) -> Result<bool, sqlx::Error> {
+  /// id of unexpired session of user with token, or with its replaced token within grace,
+  /// marking it seen now.
+  pub async fn session_id(&self, open_id: &str, token: AccessToken) -> Result<Option<u64>, sqlx::Error> {
+    let now = Utc::now();
+    let hash = token.hash();
     let sql =
-      "SELECT COUNT(*) \
-       FROM Prospect.tokenMap \
-       WHERE open_id = ? \
-       AND Prospect.tokenMap.access_token = ? \
-       AND Prospect.tokenMap.expired_time > ?";
-    let r: (i32, ) = sqlx::query_as(sql)
+      "SELECT id FROM Prospect.wechatSession \
+       WHERE open_id = ? AND (token_hash = ? AND expired_time > ? OR prev_token_hash = ? AND prev_expired_time > ?)";
+    let row: Option<(u64, )> = sqlx::query_as(sql)
+      .bind(open_id)
+      .bind(&hash)
+      .bind(now)
+      .bind(&hash)
+      .bind(now)
+      .fetch_optional(&self.pool).await?;
+    if let Some((id, )) = row {
+      sqlx::query("UPDATE Prospect.wechatSession SET last_seen_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool).await?;
+    }
+    Ok(row.map(|(id, )| id))
+  }
+
+  /// unexpired sessions of user, most recently seen first.
+  pub async fn get_sessions(&self, open_id: &str) -> Result<Vec<SessionRecord>, sqlx::Error> {
+    let sql =
+      "SELECT id, device, created_at, last_seen_at, expired_time FROM Prospect.wechatSession \
+       WHERE open_id = ? AND expired_time > ? \
+       ORDER BY last_seen_at DESC, id DESC";
+    let rows: Vec<SessionRow> = sqlx::query_as(sql)
       .bind(open_id)
-      .bind(&token.token)
       .bind(Utc::now())
-      .fetch_one(&self.pool).await?;
-    match r {
-      (0, ) => Ok(false),
-      _ => Ok(true),
+      .fetch_all(&self.pool).await?;
+    let sessions = rows
+      .into_iter()
+      .map(|(id, device, created_at, last_seen_at, expired)| SessionRecord { id, device, created_at, last_seen_at, expired })
+      .collect();
+    Ok(sessions)
+  }
+
+  /// revoke a session of user, RowNotFound if user has no such session.
+  pub async fn revoke_session(&self, open_id: &str, session_id: u64) -> Result<(), sqlx::Error> {
+    let r = sqlx::query("DELETE FROM Prospect.wechatSession WHERE id = ? AND open_id = ?")
+      .bind(session_id)
+      .bind(open_id)
+      .execute(&self.pool).await?;
+    match r.rows_affected() {
+      0 => Err(sqlx::Error::RowNotFound),
+      _ => Ok(()),
     }
   }
 
-  pub async fn wechat_notify(&self, university_id: u32, department_id: u32, ctx: Context) -> Result<(), Box<dyn std::error::Error>> {
-    let users = self.get_users(university_id, department_id).await?;
-    let mut users = users.into_iter().map(|u| (u, 5)).collect::<VecDeque<_>>();
-    let access_token = get_access_token(ctx.clone()).await?;
-    let url = format!("https://api.weixin.qq.com/cgi-bin/message/subscribe/send?access_token={}", access_token);
-    warn!("request to wechat server for notification");
-    // get university and department name
-    let university = self.get_university_name(university_id).await?;
-    let department = self.get_department_name(university_id, department_id).await?;
-    // prepare POST for wechat server
-    // let data = TestSendMessageTemplate::new("tsinghua".into(), "2020-01-01".into());
-    let data =
-      SubscribeTemplate::new(
-        university,
-        department,
-        Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-      );
-    let mut post_struct = SendMessage {
-      template_id: "TMFuXpbbjg21tEN1c4D_kHGtsNuRccqo7ft3aBC2J6s".to_string(),
-      touser: "".to_string(),
-      data: data,
-      miniprogram_state: "developer".to_string(),
-      lang: "zh_CN".to_string(),
-    };
-    // send POST request to wechat server
-    // try at most 5 times for each user
-    let client = reqwest::Client::new();
-    let mut failed_users = Vec::<(String, Error)>::new();
-    while let Some(mut user) = users.pop_front() {
-      let (ref user_id, ref mut times) = user;
-      post_struct.touser = user_id.clone();
-      *times -= 1;
-      let res = client.post(&url)
-        .json(&post_struct)
-        .send()
-        .await?;
-      if res.status().is_success() {
-        match res.json::<SendMessageResult>().await {
-          Ok(obj) => if obj.errcode != 0 {
-            failed_users.push((user_id.clone(), obj.errcode.into()));
-          } else {
-            info!("send message to user {} successfully", user_id);
-            // remove user from subscribe database
-            // construct SubscribeInfo
-            let info = SubscribeInfo {
-              open_id: user_id.clone(),
-              access_token: "".into(),
-              info: vec![SubscribeDetail {
-                school_code: university_id,
-                department_code: department_id,
-                oper: 1,
-              }],
-            };
-            self.subscribe_user(info).await?;
-          },
-          Err(_) => failed_users.push((user_id.clone(), Error::InvalidJsonFromWechat)),
-        }
-      } else {
-        if *times > 0 {
-          users.push_back(user);
-        } else {
-          failed_users.push((user_id.clone(), Error::NetworkToWechatErr));
-        }
-      }
+  /// revoke every session of user, return number of sessions revoked.
+  pub async fn revoke_sessions(&self, open_id: &str) -> Result<u64, sqlx::Error> {
+    let r = sqlx::query("DELETE FROM Prospect.wechatSession WHERE open_id = ?")
+      .bind(open_id)
+      .execute(&self.pool).await?;
+    Ok(r.rows_affected())
+  }
+}
+
+/// Refresh of mini-program access token, yields the new token and its expiry.
+pub type TokenRefresh<'a> = Pin<Box<dyn Future<Output=Result<(String, DateTime<Utc>), Error>> + Send + 'a>>;
+
+// impl for shared mini-program access token
+impl ProspectSqlPool {
+  /// Get mini-program access token shared by all server instances, running refresh if less than
+  /// refresh_ahead of its lifetime left. The token row is locked until refresh finishes, so only
+  /// one instance refreshes and the others wait for and reuse its token.
+  pub async fn shared_wechat_token(&self, refresh_ahead: Duration, refresh: TokenRefresh<'_>) -> Result<(String, DateTime<Utc>), Error> {
+    let mut tx = self.pool.begin().await.map_err(|_| Error::DatabaseErr)?;
+    let current: (String, DateTime<Utc>) =
+      sqlx::query_as("SELECT access_token, expired_time FROM Prospect.wechatToken WHERE id = 1 FOR UPDATE")
+        .fetch_one(&mut tx).await
+        .map_err(|_| Error::DatabaseErr)?;
+    if !current.0.is_empty() && current.1 - Utc::now() > refresh_ahead {
+      tx.commit().await.map_err(|_| Error::DatabaseErr)?;
+      return Ok(current);
     }
-    if !failed_users.is_empty() {
-      Err(format!("failed to send message to users: {:?}", failed_users).into())
-    } else {
-      Ok(())
+    match refresh.await {
+      Ok((access_token, expired_time)) => {
+        sqlx::query("UPDATE Prospect.wechatToken SET access_token = ?, expired_time = ? WHERE id = 1")
+          .bind(&access_token)
+          .bind(expired_time)
+          .execute(&mut tx).await
+          .map_err(|_| Error::DatabaseErr)?;
+        tx.commit().await.map_err(|_| Error::DatabaseErr)?;
+        Ok((access_token, expired_time))
+      }
+      // refreshing ahead failed, current token still works for now
+      Err(e) if !current.0.is_empty() && current.1 > Utc::now() => {
+        warn!("refresh shared access token failed: {:?}, keep using current one", e);
+        Ok(current)
+      }
+      Err(e) => Err(e),
     }
   }
 }
 
 // impl for send_code
 impl ProspectSqlPool {
-  pub async fn wechat_record_token(&self, token: AccessToken, ctx: Context) -> Result<(), sqlx::Error> {
-    self.record_token_helper(
-      token,
-      &ctx.session.as_ref().unwrap().openid.as_ref().unwrap(),
-    ).await
-  }
-
-  async fn record_token_helper(&self, token: AccessToken, open_id: &str) -> Result<(), sqlx::Error> {
+  /// start a new session of user on device, dropping expired sessions of the user.
+  pub async fn record_token(&self, open_id: &str, token: AccessToken, device: &str) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = self.pool.begin().await?;
+    sqlx::query("DELETE FROM Prospect.wechatSession WHERE open_id = ? AND expired_time <= ?")
+      .bind(open_id)
+      .bind(now)
+      .execute(&mut tx).await?;
     let sql =
-      "INSERT INTO Prospect.tokenMap (open_id, access_token, expired_time) \
-       VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE access_token = ?, expired_time = ?";
+      "INSERT INTO Prospect.wechatSession \
+       (open_id, token_hash, device, created_at, last_seen_at, expired_time) \
+       VALUES (?, ?, ?, ?, ?, ?)";
     sqlx::query(sql)
       .bind(open_id)
-      .bind(&token.token)
-      .bind(&token.expired)
-      .bind(&token.token)
-      .bind(&token.expired)
-      .execute(&self.pool).await?;
+      .bind(token.hash())
+      .bind(device)
+      .bind(now)
+      .bind(now)
+      .bind(token.expired)
+      .execute(&mut tx).await?;
+    tx.commit().await?;
     Ok(())
   }
 
-  /// check token passed in if expired, then update time if not.
-  pub async fn valid_token_and_update(&self, token: AccessToken, open_id: &str) -> Result<AccessToken, sqlx::Error> {
+  /// check token passed in if expired, then replace token of its session if due for rotation.
+  /// A token replaced within grace is still accepted and returned with its shortened expiry,
+  /// the token replacing it cannot be returned as only its hash is kept.
+  pub async fn valid_token_and_update(&self, token: AccessToken, open_id: &str, rotation: TokenRotation) -> Result<AccessToken, sqlx::Error> {
+    let now = Utc::now();
+    let hash = token.hash();
     let mut tx = sqlx::Pool::begin(&self.pool).await?;
 
-    // query if there is a valid access token
+    // query if there is a valid access token, current or replaced within grace
     let sql =
-      "SELECT open_id FROM Prospect.tokenMap WHERE open_id = ? AND access_token = ? AND expired_time > ?";
-    let rows: (String, ) = sqlx::query_as(sql)
+      "SELECT id, token_hash, expired_time, prev_expired_time FROM Prospect.wechatSession \
+       WHERE open_id = ? AND (token_hash = ? AND expired_time > ? OR prev_token_hash = ? AND prev_expired_time > ?) \
+       FOR UPDATE";
+    let (id, current, expired, prev_expired): TokenRow = sqlx::query_as(sql)
       .bind(open_id)
-      .bind(&token.token)
-      .bind(Utc::now())
+      .bind(&hash)
+      .bind(now)
+      .bind(&hash)
+      .bind(now)
       .fetch_one(&mut tx).await?;
 
-    // update if there is a valid access token
-    let new_token = AccessToken::new(&rows.0);
+    let kept = if current != hash {
+      // replaced by a request sent in parallel
+      Some(AccessToken { token: token.token, expired: prev_expired.unwrap_or(now) })
+    } else if !rotation.is_due(expired) {
+      Some(AccessToken { token: token.token, expired })
+    } else {
+      None
+    };
+    if let Some(kept) = kept {
+      sqlx::query("UPDATE Prospect.wechatSession SET last_seen_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(&mut tx).await?;
+      tx.commit().await?;
+      return Ok(kept);
+    }
+
+    // replace token, keeping the old one for grace
+    let new_token = AccessToken::new();
     let sql =
-      "UPDATE Prospect.tokenMap \
-       SET access_token = ?, expired_time = ? \
-       WHERE open_id = ?";
+      "UPDATE Prospect.wechatSession \
+       SET prev_token_hash = ?, prev_expired_time = ?, token_hash = ?, expired_time = ?, last_seen_at = ? \
+       WHERE id = ?";
     sqlx::query(sql)
-      .bind(&new_token.token)
-      .bind(&new_token.expired)
-      .bind(&rows.0)
+      .bind(&hash)
+      .bind(rotation.grace_expired(expired))
+      .bind(new_token.hash())
+      .bind(new_token.expired)
+      .bind(now)
+      .bind(id)
       .execute(&mut tx).await?;
     tx.commit().await?;
 
@@ -159,30 +206,11 @@ impl ProspectSqlPool {
 
 // impl for subscribe
 impl ProspectSqlPool {
-  pub async fn wechat_subscribe(&self, info: SubscribeInfo, ctx: Context) -> Result<(), sqlx::Error> {
-    self.subscribe_user(info).await?;
-
-    Ok(())
-  }
-
-  pub async fn wechat_get_subscribe(&self, info: GetSubscribeInfo, ctx: Context) -> Result<HashMap<u32, Vec<u32>>, sqlx::Error> {
-    let table_name = format!("UserSubMap.u{}", info.open_id);
-    let sql =
-      format!("SELECT university_id, department_id FROM {}", table_name);
-    let rows: Vec<(u32, u32)> =
-      sqlx::query_as(&sql)
-        .fetch_all(&self.pool).await
-        .map_or_else(|e| {
-          match e {
-            sqlx::Error::Database(ref ne) =>
-              match ne.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>() {
-                Some(ne) => if ne.number() == 1146 { Ok(Vec::new()) } else { Err(e) }
-                None => Err(e)
-              },
-            _ => Err(e),
-          }
-        }, |data| Ok(data),
-        )?;
+  pub async fn get_subscriptions(&self, open_id: &str) -> Result<HashMap<u32, Vec<u32>>, sqlx::Error> {
+    let sql = "SELECT university_id, department_id FROM Prospect.subscriptions WHERE open_id = ?";
+    let rows: Vec<(u32, u32)> = sqlx::query_as(sql)
+      .bind(open_id)
+      .fetch_all(&self.pool).await?;
     let map = rows
       .into_iter()
       .fold(HashMap::new(), |mut map, (uni_id, dep_id)| {
@@ -192,8 +220,8 @@ impl ProspectSqlPool {
     Ok(map)
   }
 
-  pub async fn wechat_get_university(&self) -> Result<HashMap<u32, String>, sqlx::Error> {
-    let sql = "SELECT id, name FROM UniUserMap.university";
+  pub async fn get_universities(&self) -> Result<HashMap<u32, String>, sqlx::Error> {
+    let sql = "SELECT id, name FROM Prospect.universities";
     let rows: Vec<(u32, String)> = sqlx::query_as(sql).fetch_all(&self.pool).await?;
     let map = rows
       .into_iter()
@@ -201,13 +229,13 @@ impl ProspectSqlPool {
     Ok(map)
   }
 
-  pub async fn wechat_get_department(&self, university_id: u32) -> Result<HashMap<u32, String>, sqlx::Error> {
-    let sql = "SELECT uni_name FROM UniUserMap.university WHERE id = ?";
-    let university_name: (String, ) = sqlx::query_as(sql)
+  pub async fn get_departments(&self, university_id: u32) -> Result<HashMap<u32, String>, sqlx::Error> {
+    // unknown university is reported as RowNotFound
+    sqlx::query("SELECT id FROM Prospect.universities WHERE id = ?")
       .bind(university_id)
       .fetch_one(&self.pool).await?;
-    let sql = format!("SELECT id, department_name FROM UniUserMap.{}", university_name.0);
-    let rows: Vec<(u32, String)> = sqlx::query_as(&sql).bind(university_id).fetch_all(&self.pool).await?;
+    let sql = "SELECT id, name FROM Prospect.departments WHERE university_id = ?";
+    let rows: Vec<(u32, String)> = sqlx::query_as(sql).bind(university_id).fetch_all(&self.pool).await?;
     let map = rows
       .into_iter()
       .collect::<HashMap<_, _>>();
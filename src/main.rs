@@ -53,7 +53,7 @@ async fn main() -> io::Result<()> {
     .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
   let certs = load_certs(&options.cert)?;
   let mut keys = load_keys(&options.key)?;
-  if keys.len() == 0 {
+  if keys.is_empty() {
     panic!("no valid keys found");
   }
   let flag_echo = options.echo_mode;
@@ -1,28 +1,49 @@
+use chrono::{DateTime, Duration, Utc};
+use crypto::digest::Digest;
+use rand::Rng;
 use serde::{Serialize, Deserialize};
 
-/// /send_code receive
-#[derive(Deserialize, Serialize, Debug)]
+/// /signin receive
+#[derive(Deserialize, Serialize)]
 pub struct SignUpInfo {
   pub username: String,
   pub password: String,
 }
 
-/// /send_code return
+impl std::fmt::Debug for SignUpInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SignUpInfo")
+      .field("username", &self.username)
+      .field("password", &redact(&self.password))
+      .finish()
+  }
+}
+
+/// /signin return
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SignUpResult {
   pub success: bool,
   pub message: String,
 }
 
-/// /log_in receive
-#[derive(Deserialize, Serialize, Debug)]
+/// /login receive
+#[derive(Deserialize, Serialize)]
 pub struct LogInInfo {
   pub username: String,
   pub password: String,
 }
 
-/// /log_in return
-#[derive(Deserialize, Serialize, Debug)]
+impl std::fmt::Debug for LogInInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("LogInInfo")
+      .field("username", &self.username)
+      .field("password", &redact(&self.password))
+      .finish()
+  }
+}
+
+/// /login return
+#[derive(Deserialize, Serialize)]
 pub struct LogInResult {
   pub success: bool,
   pub message: String,
@@ -30,12 +51,81 @@ pub struct LogInResult {
   pub access_token: String,
 }
 
+impl std::fmt::Debug for LogInResult {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("LogInResult")
+      .field("success", &self.success)
+      .field("message", &self.message)
+      .field("user_id", &self.user_id)
+      .field("access_token", &redact(&self.access_token))
+      .finish()
+  }
+}
+
+/// /logout return, also replied to requests rejected for lack of a valid session
 #[derive(Deserialize, Serialize, Debug)]
-pub struct AccessToken;
+pub struct LogOutResult {
+  pub success: bool,
+  pub message: String,
+}
+
+/// token issued to web user on log in, recorded as a session until expired or logged out
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AccessToken {
+  pub token: String,
+  pub expired: DateTime<Utc>,
+}
+
+impl std::fmt::Debug for AccessToken {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("AccessToken")
+      .field("token", &redact(&self.token))
+      .field("expired", &self.expired)
+      .finish()
+  }
+}
+
+impl AccessToken {
+  /// Generate a random token valid for 3 days.
+  pub fn new() -> Self {
+    AccessToken {
+      token: random_token(),
+      expired: Utc::now() + Duration::days(3),
+    }
+  }
+
+  /// Hex SHA3-256 of token, the only form of it kept in database.
+  pub fn hash(&self) -> String {
+    hash_token(&self.token)
+  }
+}
+
+/// 32 random bytes from OS random source, hex encoded, for tokens of web and mini-program users.
+pub fn random_token() -> String {
+  let bytes = rand::rngs::OsRng.gen::<[u8; 32]>();
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// What Debug prints of a secret, so that requests carrying one can be logged.
+pub(crate) fn redact(secret: &str) -> &'static str {
+  if secret.is_empty() { "" } else { "***" }
+}
+
+/// Hex SHA3-256 of a token presented by a client, to look up sessions by.
+pub fn hash_token(token: &str) -> String {
+  let mut hasher = crypto::sha3::Sha3::sha3_256();
+  hasher.input(token.as_bytes());
+  hasher.result_str()
+}
+
+impl Default for AccessToken {
+  fn default() -> Self {
+    Self::new()
+  }
+}
 
-impl Into<String> for AccessToken {
-  fn into(self) -> String {
-    // todo!()
-    "".to_string()
+impl From<AccessToken> for String {
+  fn from(value: AccessToken) -> Self {
+    value.token
   }
 }
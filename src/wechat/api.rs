@@ -0,0 +1,86 @@
+//! wechat server api used by backend
+
+use async_trait::async_trait;
+
+use super::common::{get_json_from_url, post_json_to_url};
+use super::to_wechat_types::{SendMessage, SendMessageResult};
+use super::types::{Code2SessionResponse, Error, GetAccessTokenResponse, Options};
+
+/// Wechat server api, non-zero errcode in reply is returned as Error.
+#[async_trait]
+pub trait WechatApi: std::fmt::Debug + Send + Sync {
+  /// auth.code2Session, exchange login code of mini-program for open_id.
+  async fn code2session(&self, code: &str) -> Result<Code2SessionResponse, Error>;
+
+  /// auth.getAccessToken, fetch a new access token of mini-program.
+  async fn get_access_token(&self) -> Result<GetAccessTokenResponse, Error>;
+
+  /// subscribeMessage.send, send a subscription message to user.
+  async fn send_subscribe_message(&self, access_token: &str, message: &SendMessage) -> Result<(), Error>;
+}
+
+/// WechatApi over http, talking to wechat server or anything serving the same api.
+#[derive(Clone)]
+pub struct HttpWechatApi {
+  base_url: String,
+  app_id: String,
+  app_secret: String,
+}
+
+impl HttpWechatApi {
+  pub fn new(base_url: &str, app_id: &str, app_secret: &str) -> Self {
+    HttpWechatApi {
+      base_url: base_url.trim_end_matches('/').to_string(),
+      app_id: app_id.to_string(),
+      app_secret: app_secret.to_string(),
+    }
+  }
+
+  pub fn from_options(options: &Options) -> Self {
+    Self::new(&options.wx_api_base, &options.wx_appid, &options.wx_appsecret)
+  }
+
+  fn url(&self, path: &str, params: &[(&str, &str)]) -> Result<reqwest::Url, Error> {
+    reqwest::Url::parse_with_params(&format!("{}{}", self.base_url, path), params)
+      .map_err(|_| Error::NetworkToWechatErr)
+  }
+}
+
+/// app secret is redacted, context holding the api is printed at startup.
+impl std::fmt::Debug for HttpWechatApi {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("HttpWechatApi")
+      .field("base_url", &self.base_url)
+      .field("app_id", &self.app_id)
+      .field("app_secret", &"***")
+      .finish()
+  }
+}
+
+#[async_trait]
+impl WechatApi for HttpWechatApi {
+  async fn code2session(&self, code: &str) -> Result<Code2SessionResponse, Error> {
+    let url = self.url("/sns/jscode2session", &[
+      ("appid", &self.app_id),
+      ("secret", &self.app_secret),
+      ("js_code", code),
+      ("grant_type", "authorization_code"),
+    ])?;
+    get_json_from_url(url.as_str()).await
+  }
+
+  async fn get_access_token(&self) -> Result<GetAccessTokenResponse, Error> {
+    let url = self.url("/cgi-bin/token", &[
+      ("grant_type", "client_credential"),
+      ("appid", &self.app_id),
+      ("secret", &self.app_secret),
+    ])?;
+    get_json_from_url(url.as_str()).await
+  }
+
+  async fn send_subscribe_message(&self, access_token: &str, message: &SendMessage) -> Result<(), Error> {
+    let url = self.url("/cgi-bin/message/subscribe/send", &[("access_token", access_token)])?;
+    post_json_to_url::<SendMessageResult, _>(url.as_str(), message).await?;
+    Ok(())
+  }
+}
@@ -0,0 +1,138 @@
+//! authentication of web users by session token, and of mini-program users by open_id and access token
+
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection, Reply};
+
+use crate::types::LogOutResult;
+
+use super::types::{is_valid_open_id, Context, Error, ErrorResult};
+
+/// header carrying open_id of mini-program user, in place of open_id field of json body
+pub const OPEN_ID_HEADER: &str = "x-open-id";
+/// header carrying access token of mini-program user, in place of access_token field of json body
+pub const ACCESS_TOKEN_HEADER: &str = "x-access-token";
+
+/// web user resolved from session token of request
+#[derive(Debug, Clone)]
+pub struct WebUser {
+  pub user_id: u32,
+  pub access_token: String,
+}
+
+/// request without a valid session token, replied by handle_rejection
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Resolve `Authorization: Bearer <token>` header of request to the web user logged in with it,
+/// rejecting with Unauthorized if the header is missing or the session expired or was revoked.
+pub fn with_web_user(ctx: Context) -> impl Filter<Extract=(WebUser, ), Error=Rejection> + Clone {
+  warp::header::optional::<String>("authorization")
+    .and_then(move |header: Option<String>| {
+      let ctx = ctx.clone();
+      async move {
+        let token = header
+          .as_deref()
+          .and_then(|h| h.strip_prefix("Bearer "))
+          .map(str::trim)
+          .filter(|t| !t.is_empty())
+          .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+        match ctx.store.web_session_user(token).await {
+          Ok(user_id) => Ok(WebUser { user_id, access_token: token.to_string() }),
+          Err(e) => {
+            info!("session token rejected: {:?}", e);
+            Err(warp::reject::custom(Unauthorized))
+          }
+        }
+      }
+    })
+}
+
+/// mini-program user authenticated by open_id and access token of request
+#[derive(Debug, Clone)]
+pub struct WechatUser {
+  pub open_id: String,
+  pub access_token: String,
+  /// id of session the access token belongs to
+  pub session_id: u64,
+}
+
+/// request of mini-program user rejected with error, replied by handle_rejection
+#[derive(Debug)]
+pub struct WechatRejection(pub Error);
+
+impl warp::reject::Reject for WechatRejection {}
+
+/// open_id and access_token fields of json body
+#[derive(Deserialize, Default)]
+struct Credentials {
+  #[serde(default)]
+  open_id: String,
+  #[serde(default)]
+  access_token: String,
+}
+
+/// Parse json body of request as T and authenticate mini-program user by `x-open-id` and
+/// `x-access-token` headers, or by open_id and access_token fields of body where a header is
+/// missing. Rejects with WechatRejection if body is invalid, open_id is malformed or token expired.
+pub fn with_wechat_user<T>(ctx: Context) -> impl Filter<Extract=(WechatUser, T), Error=Rejection> + Clone
+where
+  T: DeserializeOwned + Send + 'static,
+{
+  warp::header::optional::<String>(OPEN_ID_HEADER)
+    .and(warp::header::optional::<String>(ACCESS_TOKEN_HEADER))
+    .and(warp::body::bytes())
+    .and_then(move |open_id: Option<String>, access_token: Option<String>, body: Bytes| {
+      let ctx = ctx.clone();
+      async move {
+        let reject = |e: Error| warp::reject::custom(WechatRejection(e));
+        let info: T = serde_json::from_slice(&body).map_err(|e| {
+          info!("invalid json request: {}", e);
+          reject(Error::InvalidJsonRequest)
+        })?;
+        let fields: Credentials = serde_json::from_slice(&body).unwrap_or_default();
+        let open_id = open_id.unwrap_or(fields.open_id);
+        let access_token = access_token.unwrap_or(fields.access_token);
+        if !is_valid_open_id(&open_id) {
+          info!("malformed open_id {:?}", open_id);
+          return Err(reject(Error::InvalidOpenId));
+        }
+        match ctx.store.session_id(&open_id, access_token.clone().into()).await {
+          Ok(Some(session_id)) => Ok((WechatUser { open_id, access_token, session_id }, info)),
+          Ok(None) => {
+            info!("access token expired from {}", open_id);
+            Err(reject(Error::TokenExpired))
+          }
+          Err(_) => {
+            warn!("querying token failed caused by database");
+            Err(reject(Error::DatabaseErr))
+          }
+        }
+      }
+    })
+    .untuple_one()
+}
+
+/// Reply Unauthorized rejections with 401 and a json message, WechatRejection with its error
+/// as err_code like other replies to mini-program, leave others to warp.
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+  if err.find::<Unauthorized>().is_some() {
+    let reply = LogOutResult {
+      success: false,
+      message: "invalid or expired access token".to_string(),
+    };
+    let reply = warp::reply::json(&reply);
+    Ok(warp::reply::with_status(reply, StatusCode::UNAUTHORIZED))
+  } else if let Some(WechatRejection(e)) = err.find() {
+    let reply = warp::reply::json(&ErrorResult::from(*e));
+    Ok(warp::reply::with_status(reply, StatusCode::OK))
+  } else {
+    Err(err)
+  }
+}
+
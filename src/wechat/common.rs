@@ -1,66 +1,70 @@
-use chrono::{Duration, Utc};
-use reqwest::get;
+use std::sync::OnceLock;
+
+use log::warn;
 
 use serde::{Deserialize, Serialize};
 
 use super::types::*;
 
+/// timeout for connecting to wechat server
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// timeout for a whole request to wechat server
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Http client shared by all requests to wechat server, so that connections are pooled.
+fn client() -> &'static reqwest::Client {
+  static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+  CLIENT.get_or_init(|| {
+    reqwest::Client::builder()
+      .connect_timeout(CONNECT_TIMEOUT)
+      .timeout(REQUEST_TIMEOUT)
+      .build()
+      .expect("build http client for wechat")
+  })
+}
+
+/// Error fields every wechat reply may carry.
+#[derive(Deserialize, Debug)]
+struct WechatEnvelope {
+  errcode: Option<i32>,
+  errmsg: Option<String>,
+}
+
+/// Parse reply from wechat server, a non-zero errcode is turned into Error.
+async fn parse_wechat_reply<T: for<'de> Deserialize<'de>>(r: reqwest::Response) -> Result<T, Error> {
+  if !r.status().is_success() {
+    warn!("wechat server replied with status {}", r.status());
+    return Err(Error::NetworkToWechatErr);
+  }
+  let body = r.bytes().await.map_err(|_| Error::NetworkToWechatErr)?;
+  let envelope: WechatEnvelope = serde_json::from_slice(&body).map_err(|_| Error::InvalidJsonFromWechat)?;
+  match envelope.errcode {
+    Some(0) | None => serde_json::from_slice(&body).map_err(|_| Error::InvalidJsonFromWechat),
+    Some(err_code) => {
+      warn!("wechat server replied with errcode {} errmsg {:?}", err_code, envelope.errmsg);
+      Err(err_code.into())
+    }
+  }
+}
+
 /// Get json from specified URL.
 pub async fn get_json_from_url<T: for<'de> Deserialize<'de>>(url: &str) -> Result<T, Error> {
-  match get(url).await {
-    Ok(r) => {
-      match r.json::<T>().await {
-        Ok(j) => {
-          Ok(j)
-        }
-        Err(_) => {
-          Err(Error::InvalidJsonFromWechat)
-        }
-      }
-    }
-    Err(_) => {
-      Err(Error::NetworkToWechatErr)
-    }
+  match client().get(url).send().await {
+    Ok(r) => parse_wechat_reply(r).await,
+    Err(_) => Err(Error::NetworkToWechatErr),
   }
 }
 
 /// Make post request for json with specified post data.
 pub async fn post_json_to_url<T, U>(url: &str, post_data: U) -> Result<T, Error>
   where U: Serialize, T: for<'de> Deserialize<'de> {
-  // TODO:
-  Err(Error::InvalidJsonFromWechat)
-}
-
-pub(crate) async fn code2session(app_id: &str, app_secret: &str, code: &str) -> Result<Code2SessionResponse, Error> {
-  // construct auth.code2Session request URL
-  let code2session = format!(
-    "https://api.weixin.qq.com/sns/jscode2session?appid={}&secret={}&js_code={}&grant_type=authorization_code",
-    app_id,
-    app_secret,
-    code,
-  );
-  get_json_from_url::<Code2SessionResponse>(&code2session).await
+  match client().post(url).json(&post_data).send().await {
+    Ok(r) => parse_wechat_reply(r).await,
+    Err(_) => Err(Error::NetworkToWechatErr),
+  }
 }
 
-/// Get access token for miniprogram from wechat server.
+/// Get access token for miniprogram, cached by token manager.
 pub(crate) async fn get_access_token(ctx: Context) -> Result<String, Error> {
-  // return cached access token if it's not expired,
-  // or request from wechat server.
-  {
-    // TODO: handle err cause by unwrap
-    let ctx_lock = ctx.global_field.lock().unwrap();
-    if ctx_lock.expired_time.is_some() && &Utc::now() < ctx_lock.expired_time.as_ref().unwrap() {
-      return Ok(ctx_lock.access_token.as_ref().unwrap().clone());
-    }
-  }
-  let req = format!(
-    "https://api.weixin.qq.com/cgi-bin/token?grant_type=client_credential&appid={}&secret={}",
-    ctx.options.wx_appid,
-    ctx.options.wx_appsecret,
-  );
-  let r = get_json_from_url::<GetAccessTokenResponse>(&req).await?;
-  let mut ctx_lock = ctx.global_field.lock().unwrap();
-  ctx_lock.access_token = r.access_token.clone();
-  ctx_lock.expired_time = Some(Utc::now() + Duration::seconds(r.expires_in.unwrap() as i64));
-  Ok(r.access_token.unwrap())
+  ctx.token_manager.get(ctx.wechat.as_ref()).await
 }
@@ -2,27 +2,38 @@ use std::convert::Infallible;
 
 use log::{info, warn};
 
+use crate::database::SignUpErr;
+use crate::types::{LogInInfo, LogInResult, LogOutResult, SignUpInfo, SignUpResult};
+
+use super::auth::{WebUser, WechatUser};
+use super::outbox::notify_department;
 use super::types::*;
-use super::common::*;
 use super::types::AccessToken;
 
+/// longest device description kept for a session, as limited by session table
+const MAX_DEVICE_LEN: usize = 255;
+
 /// handler for /send_code
 pub async fn send_code_handler(info: CodeInfo, mut ctx: Context) -> Result<impl warp::Reply, Infallible> {
   info!("a request with info: {:?}", info);
   let reply = if info.access_token.is_empty() {
-    match code2session(&ctx.options.wx_appid, &ctx.options.wx_appsecret, &info.code).await {
+    match ctx.wechat.code2session(&info.code).await {
       Ok(j) => {
         info!("json {:?} from wechat server parsed successfully", j);
-        info!("require code2Session ok for code: {}", info.code);
+        info!("require code2Session ok");
         match j.errcode {
-          Some(0) | None => if j.openid.is_some() {
-            ctx.session = Some(j.clone());
-            let open_id = j.openid.unwrap();
-            let token = AccessToken::new(&open_id);
+          Some(0) | None => if let Some(open_id) = j.openid.clone() {
+            if !is_valid_open_id(&open_id) {
+              warn!("malformed open_id {:?} from wechat server", open_id);
+              return Ok(warp::reply::json(&CodeResult::new(Err(Error::InvalidOpenId))));
+            }
+            ctx.session = Some(j);
+            let token = AccessToken::new();
             info!("get json from wechat server with open_id {} and no error", open_id);
-            match ctx.pool.wechat_record_token(token.clone(), ctx.clone()).await {
+            let device: String = info.device.chars().take(MAX_DEVICE_LEN).collect();
+            match ctx.store.record_token(&open_id, token.clone(), &device).await {
               Ok(()) => {
-                info!("record access token {:?} for {} ok", token, open_id);
+                info!("record access token for {} ok, expires at {}", open_id, token.expired);
                 CodeResult::new(Ok((open_id, token)))
               }
               Err(_) => {
@@ -38,22 +49,26 @@ pub async fn send_code_handler(info: CodeInfo, mut ctx: Context) -> Result<impl
         }
       }
       Err(e) => {
-        warn!("request for code2Session failed for code {} {:?}", info.code, e);
+        warn!("request for code2Session failed: {:?}", e);
         CodeResult::new(Err(e))
       }
     }
+  } else if !is_valid_open_id(&info.open_id) {
+    info!("malformed open_id {:?} from miniprogram", info.open_id);
+    CodeResult::new(Err(Error::InvalidOpenId))
   } else {
     info!("get info with access token from miniprogram, querying database cache...");
-    match ctx.pool.valid_token_and_update(
+    match ctx.store.valid_token_and_update(
       AccessToken::from(info.access_token.clone()),
       &info.open_id,
+      ctx.options.token_rotation(),
     ).await {
       Ok(token) => {
-        info!("access token {} cache HIT!", info.access_token);
+        info!("access token of {} cache HIT!", info.open_id);
         CodeResult::new(Ok((info.open_id, token)))
       }
       Err(_) => {
-        info!("access token {} cache expired", info.access_token);
+        info!("access token of {} cache expired", info.open_id);
         CodeResult::new(Err(Error::TokenExpired))
       }
     }
@@ -62,78 +77,198 @@ pub async fn send_code_handler(info: CodeInfo, mut ctx: Context) -> Result<impl
 }
 
 // handler for waterfall
-pub async fn waterfall_handler(ctx: Context) -> Result<impl warp::Reply, Infallible> {
-  // TODO: todo!()
-  let items = vec![
-    WaterFallItem::new("".into(), "brief talking on creation".into(), "post/brief_taking_on_creational.md".into()),
-    WaterFallItem::new("".into(), "implement dup2".into(), "post/implement_dup2.md".into()),
-    WaterFallItem::new("".into(), "linux kernel stack switch".into(), "post/linux_kernel_stack_switch.md".into()),
-  ];
-  let reply = WaterFall::new(Ok(items));
+pub async fn waterfall_handler(info: WaterFallInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  info!("a request with info: {:?}", info);
+  let cursor = info.cursor.as_deref().filter(|c| !c.is_empty()).map(PostCursor::parse);
+  let reply = match cursor {
+    // department ids are only unique inside a university
+    Some(None) => WaterFall::new(Err(Error::InvalidJsonRequest)),
+    _ if info.department_id.is_some() && info.university_id.is_none() => WaterFall::new(Err(Error::InvalidJsonRequest)),
+    _ => match ctx.store.get_posts(&info, cursor.flatten()).await {
+      Ok(page) => WaterFall::new(Ok(page)),
+      Err(e) => {
+        warn!("querying posts failed caused by database: {:?}", e);
+        WaterFall::new(Err(Error::DatabaseErr))
+      }
+    }
+  };
   Ok(warp::reply::json(&reply))
 }
 
-// handler for subscribe
-pub async fn subscribe_handler(info: SubscribeInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+// handler for post_detail
+pub async fn post_detail_handler(info: PostDetailInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
   info!("a request with info: {:?}", info);
-  let reply = match ctx.pool.is_valid_access_token(&info.open_id, info.access_token.clone().into()).await {
-    Ok(true) => {
-      info!("access token valid, subscribe for {}", &info.open_id);
-      match ctx.pool.wechat_subscribe(info, ctx.clone()).await {
-        Ok(()) => SubscribeResult::new(Ok(())),
-        Err(_) => SubscribeResult::new(Err(Error::DatabaseErr)),
+  let reply = match ctx.store.get_post(&info.post_id).await {
+    Ok(post) => {
+      let path = std::path::Path::new(&ctx.options.assets_path).join(&post.asset_path);
+      match tokio::fs::read_to_string(&path).await {
+        Ok(markdown) => {
+          let (mut front_matter, content) = PostFrontMatter::parse(&markdown);
+          // fill in what front-matter does not provide from catalog
+          front_matter.title.get_or_insert(post.title);
+          front_matter.author.get_or_insert(post.author);
+          front_matter.date.get_or_insert_with(|| post.publish_date.format("%Y-%m-%d").to_string());
+          PostContent::new(Ok((front_matter, content.to_string())))
+        }
+        Err(e) => {
+          warn!("read post {} failed: {:?}", path.display(), e);
+          PostContent::new(Err(Error::PostNotFound))
+        }
       }
     }
-    Ok(false) => {
-      info!("access token expired from {}", info.open_id);
-      SubscribeResult::new(Err(Error::TokenExpired))
+    Err(sqlx::Error::RowNotFound) => {
+      info!("post {} not found", info.post_id);
+      PostContent::new(Err(Error::PostNotFound))
     }
     Err(_) => {
-      warn!("querying token failed caused by database");
-      SubscribeResult::new(Err(Error::DatabaseErr))
+      warn!("querying post failed caused by database");
+      PostContent::new(Err(Error::DatabaseErr))
     }
   };
   Ok(warp::reply::json(&reply))
 }
 
-pub async fn get_user_subscribe_handler(info: GetSubscribeInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+// handler for subscribe
+pub async fn subscribe_handler(user: WechatUser, info: SubscribeInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
   info!("a request with info: {:?}", info);
-  let reply = match ctx.pool.is_valid_access_token(&info.open_id, info.access_token.clone().into()).await {
-    Ok(true) => {
-      info!("access token valid, get subscribe for {}", &info.open_id);
-      match ctx.pool.wechat_get_subscribe(info, ctx.clone()).await {
-        Ok(sub) => GetSubscribeResult::new(Ok(sub)),
-        Err(_) => GetSubscribeResult::new(Err(Error::DatabaseErr)),
-      }
-    }
-    Ok(false) => {
-      info!("access token expired from {}", info.open_id);
-      GetSubscribeResult::new(Err(Error::TokenExpired))
+  info!("access token valid, subscribe for {}", user.open_id);
+  let info = SubscribeInfo { open_id: user.open_id, ..info };
+  let reply = match ctx.store.subscribe_user(info).await {
+    Ok(()) => SubscribeResult::new(Ok(())),
+    Err(_) => SubscribeResult::new(Err(Error::DatabaseErr)),
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+pub async fn get_user_subscribe_handler(user: WechatUser, info: GetSubscribeInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  info!("a request with info: {:?}", info);
+  info!("access token valid, get subscribe for {}", user.open_id);
+  let reply = match ctx.store.get_subscriptions(&user.open_id).await {
+    Ok(sub) => GetSubscribeResult::new(Ok(sub)),
+    Err(_) => GetSubscribeResult::new(Err(Error::DatabaseErr)),
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+// handler for sessions
+pub async fn sessions_handler(user: WechatUser, _: SessionsInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  let reply = match ctx.store.get_sessions(&user.open_id).await {
+    Ok(sessions) => SessionsResult::new(Ok((user.session_id, sessions))),
+    Err(_) => SessionsResult::new(Err(Error::DatabaseErr)),
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+// handler for revoke_session
+pub async fn revoke_session_handler(user: WechatUser, info: RevokeSessionInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  let r = match info.session_id {
+    Some(session_id) => ctx.store.revoke_session(&user.open_id, session_id).await.map(|()| 1),
+    None => ctx.store.revoke_sessions(&user.open_id).await,
+  };
+  let reply = match r {
+    Ok(revoked) => {
+      info!("revoked {} sessions of {}", revoked, user.open_id);
+      RevokeSessionResult::new(Ok(revoked))
     }
-    Err(_) => {
-      warn!("querying token failed caused by database");
-      GetSubscribeResult::new(Err(Error::DatabaseErr))
+    Err(sqlx::Error::RowNotFound) => RevokeSessionResult::new(Err(Error::InvalidJsonRequest)),
+    Err(_) => RevokeSessionResult::new(Err(Error::DatabaseErr)),
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+// handler for source
+pub async fn source_handler(user: WechatUser, info: SourceInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  info!("a request for paper {:?} of {}/{} from {:?}", info.paper, info.university_id, info.department_id, user.open_id);
+  let reply = if !info.is_valid_paper() {
+    info!("invalid paper name {:?} from {}", info.paper, user.open_id);
+    SourceContent::new(Err(Error::InvalidJsonRequest))
+  } else {
+    SourceContent::new(read_paper(&info, &ctx).await)
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+/// read paper from assets_path/paper/{university}/{department}/{paper}.
+async fn read_paper(info: &SourceInfo, ctx: &Context) -> Result<Vec<u8>, Error> {
+  let names = async {
+    let university = ctx.store.get_university_name(info.university_id).await?;
+    let department = ctx.store.get_department_name(info.university_id, info.department_id).await?;
+    Ok::<_, sqlx::Error>((university, department))
+  };
+  let (university, department) = match names.await {
+    Ok(names) => names,
+    Err(sqlx::Error::RowNotFound) => return Err(Error::SourceNotFound),
+    Err(_) => return Err(Error::DatabaseErr),
+  };
+  let path = std::path::Path::new(&ctx.options.assets_path)
+    .join("paper")
+    .join(university)
+    .join(department)
+    .join(&info.paper);
+  tokio::fs::read(&path).await.map_err(|e| {
+    info!("read paper {} failed: {:?}", path.display(), e);
+    Error::SourceNotFound
+  })
+}
+
+// handler for admin/notify
+pub async fn notify_subscription(info: NotifyInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  let reply = if ctx.is_admin(&info.admin_token) {
+    info!("notify subscribers of {}/{}", info.university_id, info.department_id);
+    let r = notify_department(&ctx, info.university_id, info.department_id).await;
+    if let Ok(ref report) = r {
+      info!("notified {} users, {} failed", report.succeeded.len(), report.failed.len());
     }
+    NotifyResult::new(r)
+  } else {
+    warn!("admin api called with invalid admin token");
+    NotifyResult::new(Err(Error::PermissionDenied))
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+// handler for admin/token_status
+pub async fn token_status_handler(info: AdminInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  let reply = if ctx.is_admin(&info.admin_token) {
+    TokenStatusResult::new(Ok(ctx.token_manager.remaining_lifetime().map(|d| d.num_seconds())))
+  } else {
+    warn!("admin api called with invalid admin token");
+    TokenStatusResult::new(Err(Error::PermissionDenied))
   };
   Ok(warp::reply::json(&reply))
 }
 
-async fn notify_subscription() {
-  // TODO:
-  // ctx.session = Some(Code2SessionResponse {
-  //   openid: Some(info.open_id),
-  //   session_key: None,
-  //   unionid: None,
-  //   errcode: None,
-  //   errmsg: None
-  // });
-  // let wechat_access_token = get_access_token(ctx.clone());
-  unimplemented!()
+// handler for admin/remove
+pub async fn remove_handler(info: RemoveInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  let reply = if ctx.is_admin(&info.admin_token) {
+    let r = match info.department_id {
+      Some(department_id) => ctx.store.remove_department(info.university_id, department_id).await,
+      None => ctx.store.remove_university(info.university_id).await,
+    };
+    match r {
+      Ok(subscriptions) => {
+        info!("removed {}/{:?} with {} subscriptions", info.university_id, info.department_id, subscriptions);
+        RemoveResult::new(Ok(subscriptions))
+      }
+      Err(sqlx::Error::RowNotFound) => {
+        info!("{}/{:?} to remove not found", info.university_id, info.department_id);
+        RemoveResult::new(Err(Error::InvalidJsonRequest))
+      }
+      Err(e) => {
+        warn!("removing {}/{:?} failed caused by database: {:?}", info.university_id, info.department_id, e);
+        RemoveResult::new(Err(Error::DatabaseErr))
+      }
+    }
+  } else {
+    warn!("admin api called with invalid admin token");
+    RemoveResult::new(Err(Error::PermissionDenied))
+  };
+  Ok(warp::reply::json(&reply))
 }
 
 pub async fn get_university_handler(ctx: Context) -> Result<impl warp::Reply, Infallible> {
   let reply = {
-    match ctx.pool.wechat_get_university().await {
+    match ctx.store.get_universities().await {
       Ok(hashmap) => UniversityResult::new(Ok(hashmap)),
       Err(sqlx::Error::RowNotFound) => UniversityResult::new(Err(Error::InvalidJsonRequest)),
       Err(_) => UniversityResult::new(Err(Error::DatabaseErr)),
@@ -144,7 +279,7 @@ pub async fn get_university_handler(ctx: Context) -> Result<impl warp::Reply, In
 
 pub async fn get_department_handler(info: GetDepartmentInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
   let reply = {
-    match ctx.pool.wechat_get_department(info.university_code).await {
+    match ctx.store.get_departments(info.university_code).await {
       Ok(hashmap) => DepartmentResult::new(Ok(hashmap)),
       Err(sqlx::Error::RowNotFound) => DepartmentResult::new(Err(Error::InvalidJsonRequest)),
       Err(_) => DepartmentResult::new(Err(Error::DatabaseErr)),
@@ -152,3 +287,74 @@ pub async fn get_department_handler(info: GetDepartmentInfo, ctx: Context) -> Re
   };
   Ok(warp::reply::json(&reply))
 }
+
+/// longest username accepted on sign up, as limited by UserAuth table
+const MAX_USERNAME_LEN: usize = 64;
+
+// handler for signin
+pub async fn sign_up_handler(info: SignUpInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  info!("sign up request for user {:?}", info.username);
+  let r = if info.username.is_empty() || info.username.len() > MAX_USERNAME_LEN || info.password.is_empty() {
+    Err(SignUpErr::InvalidInfo)
+  } else {
+    ctx.store.sign_up(info).await
+  };
+  let reply = match r {
+    Ok(()) => SignUpResult {
+      success: true,
+      message: "ok".to_string(),
+    },
+    Err(e) => {
+      info!("sign up failed: {:?}", e);
+      SignUpResult {
+        success: false,
+        message: e.into(),
+      }
+    }
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+// handler for login
+pub async fn log_in_handler(info: LogInInfo, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  info!("log in request for user {:?}", info.username);
+  let reply = match ctx.store.log_in(info).await {
+    Ok((user_id, access_token)) => LogInResult {
+      success: true,
+      message: "ok".to_string(),
+      user_id,
+      access_token: access_token.into(),
+    },
+    Err(e) => {
+      info!("log in failed: {:?}", e);
+      LogInResult {
+        success: false,
+        message: e.into(),
+        user_id: 0,
+        access_token: "".to_string(),
+      }
+    }
+  };
+  Ok(warp::reply::json(&reply))
+}
+
+// handler for logout
+pub async fn log_out_handler(user: WebUser, ctx: Context) -> Result<impl warp::Reply, Infallible> {
+  let reply = match ctx.store.log_out(&user.access_token).await {
+    Ok(()) => {
+      info!("user {} logged out", user.user_id);
+      LogOutResult {
+        success: true,
+        message: "ok".to_string(),
+      }
+    }
+    Err(e) => {
+      warn!("log out of user {} failed caused by database: {:?}", user.user_id, e);
+      LogOutResult {
+        success: false,
+        message: "unknown error".to_string(),
+      }
+    }
+  };
+  Ok(warp::reply::json(&reply))
+}
@@ -0,0 +1,162 @@
+//! in-process mock of wechat api server for tests
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+use tokio::sync::oneshot;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+use super::api::HttpWechatApi;
+
+pub const MOCK_APP_ID: &str = "mock_appid";
+pub const MOCK_APP_SECRET: &str = "mock_secret";
+pub const MOCK_ACCESS_TOKEN: &str = "mock_access_token";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MockEndpoint {
+  Code2Session,
+  GetAccessToken,
+  SendSubscribeMessage,
+}
+
+/// Scripted reply of an endpoint.
+#[derive(Clone, Debug)]
+pub enum MockReply {
+  /// reply 200 with json body
+  Json(serde_json::Value),
+  /// reply empty body with status code
+  Status(u16),
+}
+
+impl MockReply {
+  /// reply with wechat error code and message.
+  pub fn err(errcode: i32, errmsg: &str) -> Self {
+    MockReply::Json(json!({ "errcode": errcode, "errmsg": errmsg }))
+  }
+}
+
+/// A request received by mock server.
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+  pub query: HashMap<String, String>,
+  pub body: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+  replies: HashMap<MockEndpoint, VecDeque<MockReply>>,
+  requests: HashMap<MockEndpoint, Vec<MockRequest>>,
+}
+
+/// Mock wechat api server listening on localhost until dropped.
+///
+/// Each endpoint replies with scripted replies in order, then falls back to a successful
+/// default reply: open_id `mock_open_id_{js_code}` for code2Session, MOCK_ACCESS_TOKEN
+/// expiring in 7200 seconds for getAccessToken and errcode 0 for subscribeMessage.send.
+#[derive(Debug)]
+pub struct MockWechatServer {
+  addr: SocketAddr,
+  state: Arc<Mutex<MockState>>,
+  shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockWechatServer {
+  pub async fn start() -> Self {
+    let state = Arc::new(Mutex::new(MockState::default()));
+
+    let s = state.clone();
+    let route_code2session = warp::get()
+      .and(warp::path!("sns" / "jscode2session"))
+      .and(warp::query::<HashMap<String, String>>())
+      .map(move |query: HashMap<String, String>| {
+        let default = json!({
+          "openid": format!("mock_open_id_{}", query.get("js_code").cloned().unwrap_or_default()),
+          "session_key": "mock_session_key",
+        });
+        Self::handle(&s, MockEndpoint::Code2Session, query, None, default)
+      });
+
+    let s = state.clone();
+    let route_token = warp::get()
+      .and(warp::path!("cgi-bin" / "token"))
+      .and(warp::query::<HashMap<String, String>>())
+      .map(move |query: HashMap<String, String>| {
+        let default = json!({ "access_token": MOCK_ACCESS_TOKEN, "expires_in": 7200 });
+        Self::handle(&s, MockEndpoint::GetAccessToken, query, None, default)
+      });
+
+    let s = state.clone();
+    let route_send = warp::post()
+      .and(warp::path!("cgi-bin" / "message" / "subscribe" / "send"))
+      .and(warp::query::<HashMap<String, String>>())
+      .and(warp::body::json())
+      .map(move |query: HashMap<String, String>, body: serde_json::Value| {
+        let default = json!({ "errcode": 0, "errmsg": "ok" });
+        Self::handle(&s, MockEndpoint::SendSubscribeMessage, query, Some(body), default)
+      });
+
+    let (tx, rx) = oneshot::channel::<()>();
+    let (addr, server) = warp::serve(route_code2session.or(route_token).or(route_send))
+      .bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
+        rx.await.ok();
+      });
+    tokio::spawn(server);
+    MockWechatServer {
+      addr,
+      state,
+      shutdown: Some(tx),
+    }
+  }
+
+  pub fn base_url(&self) -> String {
+    format!("http://{}", self.addr)
+  }
+
+  /// WechatApi talking to this server.
+  pub fn api(&self) -> HttpWechatApi {
+    HttpWechatApi::new(&self.base_url(), MOCK_APP_ID, MOCK_APP_SECRET)
+  }
+
+  /// queue a reply for the next request to endpoint.
+  pub fn push(&self, endpoint: MockEndpoint, reply: MockReply) {
+    self.state.lock().unwrap().replies.entry(endpoint).or_default().push_back(reply);
+  }
+
+  /// requests received by endpoint so far, oldest first.
+  pub fn requests(&self, endpoint: MockEndpoint) -> Vec<MockRequest> {
+    self.state.lock().unwrap().requests.get(&endpoint).cloned().unwrap_or_default()
+  }
+
+  fn handle(
+    state: &Mutex<MockState>,
+    endpoint: MockEndpoint,
+    query: HashMap<String, String>,
+    body: Option<serde_json::Value>,
+    default: serde_json::Value,
+  ) -> warp::reply::Response {
+    let mut state = state.lock().unwrap();
+    state.requests.entry(endpoint).or_default().push(MockRequest { query, body });
+    let reply = state.replies
+      .get_mut(&endpoint)
+      .and_then(|replies| replies.pop_front())
+      .unwrap_or(MockReply::Json(default));
+    match reply {
+      MockReply::Json(v) => warp::reply::json(&v).into_response(),
+      MockReply::Status(code) => warp::reply::with_status(
+        "",
+        StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+      ).into_response(),
+    }
+  }
+}
+
+impl Drop for MockWechatServer {
+  fn drop(&mut self) {
+    if let Some(tx) = self.shutdown.take() {
+      let _ = tx.send(());
+    }
+  }
+}
@@ -2,3 +2,10 @@ pub mod types;
 pub mod handlers;
 pub mod common;
 pub mod to_wechat_types;
+pub mod watcher;
+pub mod outbox;
+pub mod api;
+pub mod token;
+pub mod auth;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
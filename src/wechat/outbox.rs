@@ -0,0 +1,151 @@
+//! delivery of subscription messages recorded in outbox
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{info, warn};
+
+use super::common::get_access_token;
+use super::to_wechat_types::SendMessage;
+use super::types::{Context, Error, NotifyReport, OutboxMessage, SubscribeDetail, SubscribeInfo, PAPER_UPDATE_TEMPLATE};
+
+/// attempts before a message is given up
+pub const MAX_ATTEMPTS: u32 = 8;
+/// delay before first retry, doubled on each further attempt
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 3600;
+/// how long a claimed message is hidden from other workers
+const LEASE_SECS: i64 = 60;
+/// messages taken from outbox on each tick
+const BATCH: u32 = 32;
+
+/// Deliver due messages in outbox every interval.
+pub async fn run_outbox(ctx: Context, interval: Duration) {
+  info!("delivering outbox every {:?}", interval);
+  let mut ticker = tokio::time::interval(interval);
+  loop {
+    ticker.tick().await;
+    let due = match ctx.store.outbox_due(MAX_ATTEMPTS, BATCH).await {
+      Ok(due) => due,
+      Err(e) => {
+        warn!("querying outbox failed caused by database: {:?}", e);
+        continue;
+      }
+    };
+    for message in due {
+      match ctx.store.outbox_claim(message.id, Utc::now() + chrono::Duration::seconds(LEASE_SECS)).await {
+        Ok(true) => {
+          let _ = deliver(&ctx, &message).await;
+        }
+        Ok(false) => {}
+        Err(e) => warn!("claim outbox message {} failed: {:?}", message.id, e),
+      }
+    }
+  }
+}
+
+/// unknown university or department is a bad request, anything else is a database error.
+fn notify_err(e: sqlx::Error) -> Error {
+  match e {
+    sqlx::Error::RowNotFound => Error::InvalidJsonRequest,
+    _ => Error::DatabaseErr,
+  }
+}
+
+/// notify subscribers of a department through outbox, failed messages are retried in background.
+pub async fn notify_department(ctx: &Context, university_id: u32, department_id: u32) -> Result<NotifyReport, Error> {
+  let users = ctx.store.get_users(university_id, department_id).await.map_err(notify_err)?;
+  // get university and department name
+  let university = ctx.store.get_university_name(university_id).await.map_err(notify_err)?;
+  let department = ctx.store.get_department_name(university_id, department_id).await.map_err(notify_err)?;
+  // render message for each user
+  let template = ctx.templates.get(PAPER_UPDATE_TEMPLATE).ok_or(Error::TemplateIdInvalid)?;
+  let vars = HashMap::from([
+    ("university", university),
+    ("department", department),
+    ("time", Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()),
+  ]);
+  let messages = users
+    .into_iter()
+    .map(|open_id| {
+      let payload = serde_json::to_string(&template.render(&open_id, &vars)).unwrap();
+      OutboxMessage::new(open_id, university_id, department_id, PAPER_UPDATE_TEMPLATE.to_string(), payload)
+    })
+    .collect();
+  dispatch(ctx, messages).await
+}
+
+/// Record messages in outbox and attempt each of them once right now,
+/// messages failed are left to run_outbox for retry.
+pub async fn dispatch(ctx: &Context, mut messages: Vec<OutboxMessage>) -> Result<NotifyReport, Error> {
+  // keep worker away while we are sending
+  let lease_until = Utc::now() + chrono::Duration::seconds(LEASE_SECS);
+  messages.iter_mut().for_each(|m| m.next_attempt_at = lease_until);
+  let messages = ctx.store.outbox_enqueue(messages).await.map_err(|_| Error::DatabaseErr)?;
+  let mut report = NotifyReport::default();
+  for message in messages {
+    match deliver(ctx, &message).await {
+      Ok(()) => report.succeeded.push(message.open_id),
+      Err(e) => report.failed.push((message.open_id, e)),
+    }
+  }
+  Ok(report)
+}
+
+/// Attempt to send a message once and record the outcome in outbox.
+/// The user is unsubscribed from the department once delivered,
+/// since a subscription message can only be sent once.
+pub async fn deliver(ctx: &Context, message: &OutboxMessage) -> Result<(), Error> {
+  let r = send(ctx, &message.payload).await;
+  let attempts = message.attempts + 1;
+  let recorded = match r {
+    Ok(()) => {
+      info!("send message {} to user {} successfully", message.id, message.open_id);
+      let info = SubscribeInfo {
+        open_id: message.open_id.clone(),
+        access_token: "".into(),
+        info: vec![SubscribeDetail {
+          school_code: message.university_id,
+          department_code: message.department_id,
+          oper: 1,
+        }],
+      };
+      if let Err(e) = ctx.store.subscribe_user(info).await {
+        warn!("unsubscribe {} after notification failed: {:?}", message.open_id, e);
+      }
+      ctx.store.outbox_mark_delivered(message.id).await
+    }
+    Err(e) => {
+      let attempts = if is_retryable(e) { attempts } else { MAX_ATTEMPTS };
+      let backoff = BASE_BACKOFF_SECS
+        .saturating_mul(1 << (attempts - 1).min(16))
+        .min(MAX_BACKOFF_SECS);
+      warn!("send message {} to user {} failed on attempt {}: {:?}", message.id, message.open_id, attempts, e);
+      ctx.store.outbox_mark_failed(message.id, e, attempts, Utc::now() + chrono::Duration::seconds(backoff)).await
+    }
+  };
+  if let Err(e) = recorded {
+    warn!("record outcome of outbox message {} failed: {:?}", message.id, e);
+  }
+  r
+}
+
+/// errors caused by the message or user themselves will not go away by retrying.
+fn is_retryable(e: Error) -> bool {
+  !matches!(
+    e,
+    Error::ToUserOrOpenIdInvalid
+      | Error::TemplateIdInvalid
+      | Error::PagePathInvalid
+      | Error::NeedSubscribe
+      | Error::TemplateParamAmbiguous
+      | Error::HighRiskUser
+  )
+}
+
+async fn send(ctx: &Context, payload: &str) -> Result<(), Error> {
+  let message: SendMessage = serde_json::from_str(payload).map_err(|_| Error::InvalidJsonRequest)?;
+  let access_token = get_access_token(ctx.clone()).await?;
+  ctx.wechat.send_subscribe_message(&access_token, &message).await
+}
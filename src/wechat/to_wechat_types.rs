@@ -1,13 +1,18 @@
+use std::collections::BTreeMap;
+
 use serde::{Serialize, Deserialize};
 
+use super::types::MiniProgramState;
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SendMessage {
   pub template_id: String,
-  // pub page: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub page: Option<String>,
   /// user's open_id
   pub touser: String,
-  pub data: SubscribeTemplate,
-  pub miniprogram_state: String,
+  pub data: BTreeMap<String, Value>,
+  pub miniprogram_state: MiniProgramState,
   pub lang: String,
 }
 
@@ -23,8 +28,8 @@ pub struct TestSendMessage {
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-struct Value {
-  value: String,
+pub struct Value {
+  pub value: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -33,24 +38,6 @@ pub struct SendMessageResult {
   pub errmsg: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct SubscribeTemplate {
-  thing1: Value,
-  thing2: Value,
-  time3: Value,
-}
-
-impl SubscribeTemplate {
-  pub fn new(university: String, department: String, date: String) -> Self {
-    // TODO:
-    SubscribeTemplate {
-      thing1: Value { value: university },
-      thing2: Value { value: department },
-      time3: Value { value: date },
-    }
-  }
-}
-
 #[derive(Deserialize, Serialize, Debug)]
 pub struct TestSendMessageTemplate {
   thing2: Value,
@@ -0,0 +1,141 @@
+//! access token of mini-program, shared by all requests to wechat server
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::{info, warn};
+
+use super::api::WechatApi;
+use super::types::{Error, PPool};
+
+/// delay before retrying a failed background refresh
+const RETRY_SECS: i64 = 30;
+
+#[derive(Clone)]
+struct CachedToken {
+  access_token: String,
+  expired_time: DateTime<Utc>,
+}
+
+/// access token is redacted, it is enough to call wechat api as the mini-program.
+impl std::fmt::Debug for CachedToken {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CachedToken")
+      .field("access_token", &"***")
+      .field("expired_time", &self.expired_time)
+      .finish()
+  }
+}
+
+/// Cache of mini-program access token.
+///
+/// Fetching a new token from wechat invalidates the previous one, so at most one refresh
+/// is in flight: callers arriving during a refresh wait for it and share its result.
+/// Token is refreshed once its remaining lifetime drops below refresh_ahead.
+///
+/// With a shared store, token is kept in database as well, so that server instances
+/// sharing the database use the same token and only one of them refreshes it.
+#[derive(Debug)]
+pub struct TokenManager {
+  cached: tokio::sync::Mutex<Option<CachedToken>>,
+  /// unix timestamp of expiry of cached token, 0 if there is none,
+  /// kept outside the lock so monitoring never waits for a refresh
+  expired_at: AtomicI64,
+  refresh_ahead: Duration,
+  store: Option<PPool>,
+}
+
+impl TokenManager {
+  pub fn new(refresh_ahead: Duration) -> Self {
+    TokenManager {
+      cached: tokio::sync::Mutex::new(None),
+      expired_at: AtomicI64::new(0),
+      refresh_ahead,
+      store: None,
+    }
+  }
+
+  /// token manager sharing token with other instances through database.
+  pub fn shared(refresh_ahead: Duration, store: PPool) -> Self {
+    TokenManager {
+      store: Some(store),
+      ..Self::new(refresh_ahead)
+    }
+  }
+
+  /// Get access token, refreshing it first if it is missing or about to expire.
+  pub async fn get(&self, wechat: &dyn WechatApi) -> Result<String, Error> {
+    let mut cached = self.cached.lock().await;
+    if let Some(token) = cached.as_ref() {
+      if token.expired_time - Utc::now() > self.refresh_ahead {
+        return Ok(token.access_token.clone());
+      }
+    }
+    let r = match self.store {
+      Some(ref store) => {
+        let refresh = Box::pin(async move {
+          Self::fetch(wechat).await.map(|t| (t.access_token, t.expired_time))
+        });
+        store.shared_wechat_token(self.refresh_ahead, refresh).await
+          .map(|(access_token, expired_time)| CachedToken { access_token, expired_time })
+      }
+      None => Self::fetch(wechat).await,
+    };
+    match r {
+      Ok(token) => {
+        info!("access token refreshed, expires at {}", token.expired_time);
+        self.expired_at.store(token.expired_time.timestamp(), Ordering::Relaxed);
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+      }
+      Err(e) => match cached.as_ref() {
+        // refreshing ahead failed, old token still works for now
+        Some(token) if token.expired_time > Utc::now() => {
+          warn!("refresh access token failed: {:?}, keep using current one", e);
+          Ok(token.access_token.clone())
+        }
+        _ => Err(e),
+      }
+    }
+  }
+
+  /// Remaining lifetime of cached token, None if no token has been fetched.
+  pub fn remaining_lifetime(&self) -> Option<Duration> {
+    match self.expired_at.load(Ordering::Relaxed) {
+      0 => None,
+      expired_at => Utc.timestamp_opt(expired_at, 0).single().map(|t| t - Utc::now()),
+    }
+  }
+
+  /// Keep token fresh in background, so requests rarely wait for a refresh.
+  pub async fn run_refresher(self: Arc<Self>, wechat: Arc<dyn WechatApi>) {
+    loop {
+      let wait = match self.get(wechat.as_ref()).await {
+        Ok(_) => self.remaining_lifetime()
+          .map_or(Duration::zero(), |remaining| remaining - self.refresh_ahead)
+          .max(Duration::seconds(RETRY_SECS)),
+        Err(e) => {
+          warn!("refresh access token failed: {:?}", e);
+          Duration::seconds(RETRY_SECS)
+        }
+      };
+      tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+    }
+  }
+
+  async fn fetch(wechat: &dyn WechatApi) -> Result<CachedToken, Error> {
+    let r = wechat.get_access_token().await?;
+    if let Some(err_code) = r.errcode.filter(|c| *c != 0) {
+      return Err(err_code.into());
+    }
+    match (r.access_token, r.expires_in) {
+      (Some(access_token), Some(expires_in)) if !access_token.is_empty() => Ok(CachedToken {
+        access_token,
+        expired_time: Utc::now() + Duration::seconds(expires_in as i64),
+      }),
+      _ => Err(Error::InvalidJsonFromWechat),
+    }
+  }
+}
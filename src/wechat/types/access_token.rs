@@ -1,31 +1,42 @@
 use serde::{Serialize, Deserialize};
 use chrono::{prelude::*, Duration};
-use crypto::digest::Digest;
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+use crate::types::{hash_token, random_token, redact};
+
+/// lifetime of access tokens issued to mini-program users
+fn lifetime() -> Duration {
+  Duration::days(3)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct AccessToken {
   pub token: String,
   pub expired: DateTime<Utc>,
 }
 
+impl std::fmt::Debug for AccessToken {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("AccessToken")
+      .field("token", &redact(&self.token))
+      .field("expired", &self.expired)
+      .finish()
+  }
+}
+
 impl AccessToken {
-  /// Generate an access token from open_id and current timestamp.
-  pub fn new(open_id: &str) -> Self {
-    let now_utc: DateTime<Utc> = Utc::now();
-    let expired_utc = now_utc + Duration::days(3);
-    let mut hasher = crypto::sha3::Sha3::sha3_256();
-    let b = open_id.as_bytes()
-      .iter()
-      .chain(now_utc.timestamp().to_string().as_bytes().iter())
-      .map(|x| *x)
-      .collect::<Vec<u8>>();
-    hasher.input(&b);
+  /// Generate a random access token valid for 3 days.
+  pub fn new() -> Self {
     AccessToken {
-      token: hasher.result_str(),
-      expired: expired_utc,
+      token: random_token(),
+      expired: Utc::now() + lifetime(),
     }
   }
 
+  /// Hex SHA3-256 of token, the only form of it kept in database.
+  pub fn hash(&self) -> String {
+    hash_token(&self.token)
+  }
+
   pub fn empty() -> Self {
     AccessToken {
       token: "".to_string(),
@@ -38,17 +49,44 @@ impl AccessToken {
   }
 }
 
+impl Default for AccessToken {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl From<String> for AccessToken {
   fn from(value: String) -> Self {
     AccessToken {
       token: value,
-      expired: Utc::now() + Duration::days(3),
+      expired: Utc::now() + lifetime(),
     }
   }
 }
 
-impl Into<String> for AccessToken {
-  fn into(self) -> String {
-    self.token
+impl From<AccessToken> for String {
+  fn from(value: AccessToken) -> Self {
+    value.token
   }
-}
\ No newline at end of file
+}
+
+/// When a token presented again to /send_code is replaced by a new one.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenRotation {
+  /// tokens issued less than this ago are kept as they are
+  pub rotate_after: Duration,
+  /// how long a replaced token is still accepted, so that requests sent with it in parallel succeed
+  pub grace: Duration,
+}
+
+impl TokenRotation {
+  /// if token expiring at expired is old enough to be replaced.
+  pub fn is_due(&self, expired: DateTime<Utc>) -> bool {
+    expired - Utc::now() <= lifetime() - self.rotate_after
+  }
+
+  /// expiry of a token expiring at expired when replaced now.
+  pub fn grace_expired(&self, expired: DateTime<Utc>) -> DateTime<Utc> {
+    (Utc::now() + self.grace).min(expired)
+  }
+}
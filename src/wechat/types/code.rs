@@ -2,19 +2,51 @@ use super::{AccessToken, Error};
 
 use serde::{Serialize, Deserialize};
 
-#[derive(Deserialize, Serialize, Debug)]
+use crate::types::redact;
+
+#[derive(Deserialize, Serialize)]
 pub struct CodeInfo {
   pub code: String,
   pub open_id: String,
   pub access_token: String,
+  /// device logging in, shown in session list
+  #[serde(default)]
+  pub device: String,
+}
+
+impl std::fmt::Debug for CodeInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CodeInfo")
+      .field("code", &redact(&self.code))
+      .field("open_id", &self.open_id)
+      .field("access_token", &redact(&self.access_token))
+      .field("device", &self.device)
+      .finish()
+  }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize)]
 pub struct CodeResult {
   pub err_code: i32,
   pub message: String,
   pub open_id: String,
   pub access_token: String,
+  /// Unix time in seconds access_token expires at, 0 on error. A token already replaced on
+  /// rotation by a request sent in parallel is returned as is, expiring at the end of its grace
+  /// window: of the tokens in replies, clients keep the one expiring last.
+  pub expired: i64,
+}
+
+impl std::fmt::Debug for CodeResult {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CodeResult")
+      .field("err_code", &self.err_code)
+      .field("message", &self.message)
+      .field("open_id", &self.open_id)
+      .field("access_token", &redact(&self.access_token))
+      .field("expired", &self.expired)
+      .finish()
+  }
 }
 
 impl CodeResult {
@@ -25,6 +57,7 @@ impl CodeResult {
         err_code: Error::Success.into(),
         message: Error::Success.into(),
         open_id: ctx.0,
+        expired: ctx.1.expired.timestamp(),
         access_token: ctx.1.into(),
       },
       Err(e) => CodeResult {
@@ -32,6 +65,7 @@ impl CodeResult {
         message: e.into(),
         open_id: "".into(),
         access_token: "".into(),
+        expired: 0,
       },
     }
   }
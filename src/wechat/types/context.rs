@@ -1,29 +1,47 @@
-use std::sync::{Arc, Mutex};
-use chrono::{DateTime, Utc};
+use std::sync::Arc;
 
-use super::{*};
+use subtle::ConstantTimeEq;
 
-#[derive(Debug, Clone, Default)]
-pub struct GlobalField {
-  pub(crate) access_token: Option<String>,
-  pub(crate) expired_time: Option<DateTime<Utc>>,
-}
+use super::{*};
+use crate::wechat::api::{HttpWechatApi, WechatApi};
+use crate::wechat::token::TokenManager;
 
 #[derive(Debug, Clone)]
 pub struct Context {
-  pub pool: PPool,
+  pub store: PPool,
   pub options: Arc<Options>,
-  pub global_field: Arc<Mutex<GlobalField>>,
+  pub token_manager: Arc<TokenManager>,
+  pub templates: Arc<TemplateConfig>,
+  pub wechat: Arc<dyn WechatApi>,
   pub(crate) session: Option<Code2SessionResponse>,
 }
 
 impl Context {
-  pub fn new(pool: PPool, options: Arc<Options>) -> Self {
+  pub fn new(store: PPool, options: Arc<Options>, templates: Arc<TemplateConfig>) -> Self {
+    let refresh_ahead = chrono::Duration::seconds(options.token_refresh_ahead as i64);
+    let token_manager = if options.shared_token {
+      TokenManager::shared(refresh_ahead, store.clone())
+    } else {
+      TokenManager::new(refresh_ahead)
+    };
     Context {
-      pool,
+      store,
+      wechat: Arc::new(HttpWechatApi::from_options(&options)),
+      token_manager: Arc::new(token_manager),
       options,
-      global_field: Arc::new(Mutex::new(GlobalField::default())),
+      templates,
       session: None,
     }
   }
+
+  /// use another wechat api instead of the one configured by options.
+  pub fn with_wechat_api(mut self, wechat: Arc<dyn WechatApi>) -> Self {
+    self.wechat = wechat;
+    self
+  }
+
+  /// check token passed in admin api against configured admin token.
+  pub fn is_admin(&self, token: &str) -> bool {
+    self.options.admin_token.as_ref().is_some_and(|t| !t.is_empty() && bool::from(t.as_bytes().ct_eq(token.as_bytes())))
+  }
 }
@@ -1,5 +1,7 @@
 use std::fmt::{Display, Formatter};
 
+use serde::{Serialize, Deserialize};
+
 #[derive(Copy, Clone, Debug)]
 pub enum Error {
   // wechat defined error
@@ -48,13 +50,21 @@ pub enum Error {
   DatabaseErr,
   /// Json request from miniprogram invalid
   InvalidJsonRequest,
+  /// Requested post not exist or not published
+  PostNotFound,
+  /// Requested paper not exist
+  SourceNotFound,
+  /// Admin api called without valid admin token
+  PermissionDenied,
+  /// open_id in request is malformed
+  InvalidOpenId,
   /// Unknown error
   UnknownErr,
 }
 
-impl Into<i32> for Error {
-  fn into(self) -> i32 {
-    match self {
+impl From<Error> for i32 {
+  fn from(value: Error) -> Self {
+    match value {
       // wechat defined error
       Error::Success => 0,
 
@@ -79,14 +89,18 @@ impl Into<i32> for Error {
       Error::OpenIdNotFound => 105,
       Error::DatabaseErr => 106,
       Error::InvalidJsonRequest => 107,
+      Error::PostNotFound => 108,
+      Error::SourceNotFound => 109,
+      Error::PermissionDenied => 110,
+      Error::InvalidOpenId => 111,
       Error::UnknownErr => 999,
     }
   }
 }
 
-impl Into<String> for Error {
-  fn into(self) -> String {
-    match self {
+impl From<Error> for String {
+  fn from(value: Error) -> Self {
+    match value {
       // wechat defined error
       Error::Success => "success".into(),
 
@@ -111,6 +125,10 @@ impl Into<String> for Error {
       Error::OpenIdNotFound => "open id not found".into(),
       Error::DatabaseErr => "database error".into(),
       Error::InvalidJsonRequest => "invalid json request".into(),
+      Error::PostNotFound => "post not found".into(),
+      Error::SourceNotFound => "source not found".into(),
+      Error::PermissionDenied => "permission denied".into(),
+      Error::InvalidOpenId => "invalid open_id".into(),
       Error::UnknownErr => "unknown error".into(),
     }
   }
@@ -143,6 +161,10 @@ impl From<i32> for Error {
       105 => Error::OpenIdNotFound,
       106 => Error::DatabaseErr,
       107 => Error::InvalidJsonRequest,
+      108 => Error::PostNotFound,
+      109 => Error::SourceNotFound,
+      110 => Error::PermissionDenied,
+      111 => Error::InvalidOpenId,
       _ => Error::UnknownErr,
     }
   }
@@ -155,3 +177,19 @@ impl Display for Error {
 }
 
 impl std::error::Error for Error {}
+
+/// reply of a request rejected before reaching its handler
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ErrorResult {
+  pub err_code: i32,
+  pub message: String,
+}
+
+impl From<Error> for ErrorResult {
+  fn from(value: Error) -> Self {
+    ErrorResult {
+      err_code: value.into(),
+      message: value.into(),
+    }
+  }
+}
@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+
+use super::Error;
+use crate::types::redact;
+
+/// /admin/notify receive
+#[derive(Deserialize, Serialize)]
+pub struct NotifyInfo {
+  pub admin_token: String,
+  pub university_id: u32,
+  pub department_id: u32,
+}
+
+impl std::fmt::Debug for NotifyInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("NotifyInfo")
+      .field("admin_token", &redact(&self.admin_token))
+      .field("university_id", &self.university_id)
+      .field("department_id", &self.department_id)
+      .finish()
+  }
+}
+
+/// Outcome of notifying subscribers of a department.
+#[derive(Debug, Default)]
+pub struct NotifyReport {
+  /// open_id of users notified successfully
+  pub succeeded: Vec<String>,
+  /// open_id of users failed to notify and the reason
+  pub failed: Vec<(String, Error)>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NotifyFailure {
+  pub open_id: String,
+  pub err_code: i32,
+  pub message: String,
+}
+
+/// /admin/notify return
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NotifyResult {
+  pub err_code: i32,
+  pub message: String,
+  pub succeeded: Vec<String>,
+  pub failed: Vec<NotifyFailure>,
+}
+
+impl NotifyResult {
+  pub fn new(arg: Result<NotifyReport, Error>) -> Self {
+    match arg {
+      Ok(report) => NotifyResult {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        succeeded: report.succeeded,
+        failed: report.failed
+          .into_iter()
+          .map(|(open_id, e)| NotifyFailure {
+            open_id,
+            err_code: e.into(),
+            message: e.into(),
+          })
+          .collect(),
+      },
+      Err(e) => NotifyResult {
+        err_code: e.into(),
+        message: e.into(),
+        succeeded: Vec::new(),
+        failed: Vec::new(),
+      },
+    }
+  }
+}
+
+/// /admin/token_status receive
+#[derive(Deserialize, Serialize)]
+pub struct AdminInfo {
+  pub admin_token: String,
+}
+
+impl std::fmt::Debug for AdminInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("AdminInfo")
+      .field("admin_token", &redact(&self.admin_token))
+      .finish()
+  }
+}
+
+/// /admin/token_status return
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TokenStatusResult {
+  pub err_code: i32,
+  pub message: String,
+  /// seconds until mini-program access token expires, -1 if there is no token yet
+  pub remaining_seconds: i64,
+}
+
+impl TokenStatusResult {
+  pub fn new(arg: Result<Option<i64>, Error>) -> Self {
+    match arg {
+      Ok(remaining) => TokenStatusResult {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        remaining_seconds: remaining.unwrap_or(-1),
+      },
+      Err(e) => TokenStatusResult {
+        err_code: e.into(),
+        message: e.into(),
+        remaining_seconds: -1,
+      },
+    }
+  }
+}
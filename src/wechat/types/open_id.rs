@@ -0,0 +1,10 @@
+/// max length accepted for open_id, those issued by wechat are 28 characters
+pub const MAX_OPEN_ID_LEN: usize = 64;
+
+/// Check open_id received from mini-program or wechat server,
+/// only ascii letters, digits, '_' and '-' are accepted.
+pub fn is_valid_open_id(open_id: &str) -> bool {
+  !open_id.is_empty()
+    && open_id.len() <= MAX_OPEN_ID_LEN
+    && open_id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+
+use super::Error;
+
+/// A subscription message recorded in outbox, kept after delivery for auditing.
+#[derive(Clone, Debug)]
+pub struct OutboxMessage {
+  /// 0 until recorded
+  pub id: u64,
+  pub open_id: String,
+  pub university_id: u32,
+  pub department_id: u32,
+  /// name of template in TemplateConfig
+  pub template: String,
+  /// rendered SendMessage json
+  pub payload: String,
+  pub attempts: u32,
+  pub last_error: Option<Error>,
+  pub created_at: DateTime<Utc>,
+  pub next_attempt_at: DateTime<Utc>,
+  pub delivered_at: Option<DateTime<Utc>>,
+}
+
+impl OutboxMessage {
+  pub fn new(open_id: String, university_id: u32, department_id: u32, template: String, payload: String) -> Self {
+    let now = Utc::now();
+    OutboxMessage {
+      id: 0,
+      open_id,
+      university_id,
+      department_id,
+      template,
+      payload,
+      attempts: 0,
+      last_error: None,
+      created_at: now,
+      next_attempt_at: now,
+      delivered_at: None,
+    }
+  }
+}
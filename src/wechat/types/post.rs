@@ -1,10 +1,151 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Serialize, Deserialize};
 
+use super::Error;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PostDetailInfo {
+  /// post_id from WaterFallItem
+  pub post_id: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct PostContent {
-  // TODO: more detail
+  pub err_code: i32,
+  pub message: String,
   pub title: String,
   pub date: String,
   pub author: String,
+  /// markdown without front-matter
   pub content: String,
 }
+
+impl PostContent {
+  pub fn new(arg: Result<(PostFrontMatter, String), Error>) -> Self {
+    match arg {
+      Ok((front_matter, content)) => PostContent {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        title: front_matter.title.unwrap_or_default(),
+        date: front_matter.date.unwrap_or_default(),
+        author: front_matter.author.unwrap_or_default(),
+        content,
+      },
+      Err(e) => PostContent {
+        err_code: e.into(),
+        message: e.into(),
+        title: "".to_string(),
+        date: "".to_string(),
+        author: "".to_string(),
+        content: "".to_string(),
+      },
+    }
+  }
+}
+
+/// Metadata at the head of a markdown post, e.g.
+///
+/// ```text
+/// ---
+/// title: implement dup2
+/// date: 2022-10-01
+/// author: prospect
+/// ---
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PostFrontMatter {
+  pub title: Option<String>,
+  pub date: Option<String>,
+  pub author: Option<String>,
+}
+
+impl PostFrontMatter {
+  /// split markdown into front-matter and the rest of content,
+  /// markdown without front-matter is returned as is.
+  pub fn parse(markdown: &str) -> (Self, &str) {
+    let mut front_matter = PostFrontMatter::default();
+    let rest = match markdown.strip_prefix("---\n").or_else(|| markdown.strip_prefix("---\r\n")) {
+      Some(rest) => rest,
+      None => return (front_matter, markdown),
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+      offset += line.len();
+      let line = line.trim();
+      if line == "---" {
+        return (front_matter, &rest[offset..]);
+      }
+      if let Some((key, value)) = line.split_once(':') {
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+        match key.trim() {
+          "title" => front_matter.title = Some(value),
+          "date" => front_matter.date = Some(value),
+          "author" => front_matter.author = Some(value),
+          _ => {}
+        }
+      }
+    }
+    // front-matter never closed, treat whole file as content
+    (PostFrontMatter::default(), markdown)
+  }
+
+  /// date in front-matter, either `%Y-%m-%d` or rfc3339.
+  pub fn publish_date(&self) -> Option<DateTime<Utc>> {
+    let date = self.date.as_deref()?;
+    DateTime::parse_from_rfc3339(date)
+      .map(|d| d.with_timezone(&Utc))
+      .ok()
+      .or_else(|| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+          .ok()
+          .and_then(|d| d.and_hms_opt(0, 0, 0))
+          .map(|d| DateTime::<Utc>::from_utc(d, Utc))
+      })
+  }
+}
+
+/// Publishing state of a post in the catalog.
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostStatus {
+  /// not visible in waterfall yet
+  Draft,
+  /// visible in waterfall
+  Published,
+  /// taken down, kept for history
+  Archived,
+}
+
+impl From<PostStatus> for u8 {
+  fn from(value: PostStatus) -> Self {
+    match value {
+      PostStatus::Draft => 0,
+      PostStatus::Published => 1,
+      PostStatus::Archived => 2,
+    }
+  }
+}
+
+impl From<u8> for PostStatus {
+  fn from(value: u8) -> Self {
+    match value {
+      1 => PostStatus::Published,
+      2 => PostStatus::Archived,
+      _ => PostStatus::Draft,
+    }
+  }
+}
+
+/// A row of the post catalog.
+#[derive(Clone, Debug)]
+pub struct PostRecord {
+  pub title: String,
+  pub img_source_link: String,
+  /// path relative to assets path, e.g. `post/implement_dup2.md`
+  pub asset_path: String,
+  pub author: String,
+  pub publish_date: DateTime<Utc>,
+  pub status: PostStatus,
+  /// university the post is about, None for general posts
+  pub university_id: Option<u32>,
+  pub department_id: Option<u32>,
+}
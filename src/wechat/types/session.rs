@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use super::Error;
+use crate::types::redact;
+
+/// a session of mini-program user, one for each device logged in through /send_code
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SessionRecord {
+  pub id: u64,
+  /// device reported by mini-program on log in
+  pub device: String,
+  pub created_at: DateTime<Utc>,
+  pub last_seen_at: DateTime<Utc>,
+  pub expired: DateTime<Utc>,
+}
+
+/// /sessions receive
+#[derive(Deserialize, Serialize)]
+pub struct SessionsInfo {
+  #[serde(default)]
+  pub open_id: String,
+  #[serde(default)]
+  pub access_token: String,
+}
+
+impl std::fmt::Debug for SessionsInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SessionsInfo")
+      .field("open_id", &self.open_id)
+      .field("access_token", &redact(&self.access_token))
+      .finish()
+  }
+}
+
+/// /sessions return
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SessionsResult {
+  pub err_code: i32,
+  pub message: String,
+  /// id of session the request is made with
+  pub current: u64,
+  pub sessions: Vec<SessionRecord>,
+}
+
+impl SessionsResult {
+  pub fn new(arg: Result<(u64, Vec<SessionRecord>), Error>) -> Self {
+    match arg {
+      Ok((current, sessions)) => SessionsResult {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        current,
+        sessions,
+      },
+      Err(e) => SessionsResult {
+        err_code: e.into(),
+        message: e.into(),
+        current: 0,
+        sessions: Vec::new(),
+      },
+    }
+  }
+}
+
+/// /revoke_session receive, every session of user is revoked if session_id is not given
+#[derive(Deserialize, Serialize)]
+pub struct RevokeSessionInfo {
+  #[serde(default)]
+  pub open_id: String,
+  #[serde(default)]
+  pub access_token: String,
+  pub session_id: Option<u64>,
+}
+
+impl std::fmt::Debug for RevokeSessionInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("RevokeSessionInfo")
+      .field("open_id", &self.open_id)
+      .field("access_token", &redact(&self.access_token))
+      .field("session_id", &self.session_id)
+      .finish()
+  }
+}
+
+/// /revoke_session return
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RevokeSessionResult {
+  pub err_code: i32,
+  pub message: String,
+  /// number of sessions revoked
+  pub revoked: u64,
+}
+
+impl RevokeSessionResult {
+  pub fn new(arg: Result<u64, Error>) -> Self {
+    match arg {
+      Ok(revoked) => RevokeSessionResult {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        revoked,
+      },
+      Err(e) => RevokeSessionResult {
+        err_code: e.into(),
+        message: e.into(),
+        revoked: 0,
+      },
+    }
+  }
+}
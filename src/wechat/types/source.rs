@@ -1,8 +1,78 @@
 use serde::{Serialize, Deserialize};
 
+use super::Error;
+use crate::types::redact;
+
+#[derive(Deserialize, Serialize)]
+pub struct SourceInfo {
+  #[serde(default)]
+  pub open_id: String,
+  #[serde(default)]
+  pub access_token: String,
+  pub university_id: u32,
+  pub department_id: u32,
+  /// file name of paper under department directory
+  pub paper: String,
+}
+
+impl std::fmt::Debug for SourceInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SourceInfo")
+      .field("open_id", &self.open_id)
+      .field("access_token", &redact(&self.access_token))
+      .field("university_id", &self.university_id)
+      .field("department_id", &self.department_id)
+      .field("paper", &self.paper)
+      .finish()
+  }
+}
+
+impl SourceInfo {
+  /// paper must be a plain file name, so that it can not escape department directory.
+  pub fn is_valid_paper(&self) -> bool {
+    !self.paper.is_empty()
+      && self.paper != "."
+      && self.paper != ".."
+      && !self.paper.contains(['/', '\\', '\0'])
+  }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SourceContent {
   pub err_code: i32,
   pub message: String,
+  /// file content, base64 encoded in json
+  #[serde(with = "base64_bytes")]
   pub content: Vec<u8>,
 }
+
+/// bytes as a base64 string rather than an array of numbers.
+mod base64_bytes {
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode(bytes))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    base64::decode(encoded).map_err(serde::de::Error::custom)
+  }
+}
+
+impl SourceContent {
+  pub fn new(arg: Result<Vec<u8>, Error>) -> Self {
+    match arg {
+      Ok(content) => SourceContent {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        content,
+      },
+      Err(e) => SourceContent {
+        err_code: e.into(),
+        message: e.into(),
+        content: Vec::new(),
+      },
+    }
+  }
+}
@@ -2,16 +2,29 @@ use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
 use super::Error;
+use crate::types::redact;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize)]
 pub struct SubscribeInfo {
   // pub school_code: u32,
   // pub department_code: u32,
+  #[serde(default)]
   pub open_id: String,
+  #[serde(default)]
   pub access_token: String,
   pub info: Vec<SubscribeDetail>,
 }
 
+impl std::fmt::Debug for SubscribeInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SubscribeInfo")
+      .field("open_id", &self.open_id)
+      .field("access_token", &redact(&self.access_token))
+      .field("info", &self.info)
+      .finish()
+  }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SubscribeDetail {
   pub school_code: u32,
@@ -40,12 +53,23 @@ impl SubscribeResult {
   }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize)]
 pub struct GetSubscribeInfo {
+  #[serde(default)]
   pub access_token: String,
+  #[serde(default)]
   pub open_id: String,
 }
 
+impl std::fmt::Debug for GetSubscribeInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("GetSubscribeInfo")
+      .field("access_token", &redact(&self.access_token))
+      .field("open_id", &self.open_id)
+      .finish()
+  }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct GetSubscribeResult {
   pub err_code: i32,
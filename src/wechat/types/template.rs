@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Serialize, Deserialize};
+
+use crate::wechat::to_wechat_types::{SendMessage, Value};
+
+/// Template used to notify subscribers of new papers.
+pub const PAPER_UPDATE_TEMPLATE: &str = "paper_update";
+
+/// Which version of mini-program a subscription message opens.
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MiniProgramState {
+  Developer,
+  Trial,
+  Formal,
+}
+
+/// A subscription message template registered on wechat.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MessageTemplate {
+  pub template_id: String,
+  /// page opened when user taps the message
+  #[serde(default)]
+  pub page: Option<String>,
+  pub miniprogram_state: MiniProgramState,
+  #[serde(default = "default_lang")]
+  pub lang: String,
+  /// template field name to value, `{name}` in value is replaced by variable `name`
+  pub fields: BTreeMap<String, String>,
+}
+
+fn default_lang() -> String {
+  "zh_CN".to_string()
+}
+
+impl MessageTemplate {
+  /// render message to user with variables.
+  pub fn render(&self, touser: &str, vars: &HashMap<&str, String>) -> SendMessage {
+    let data = self.fields
+      .iter()
+      .map(|(field, pattern)| {
+        (field.clone(), Value { value: substitute(pattern, vars) })
+      })
+      .collect();
+    SendMessage {
+      template_id: self.template_id.clone(),
+      page: self.page.clone(),
+      touser: touser.to_string(),
+      data,
+      miniprogram_state: self.miniprogram_state,
+      lang: self.lang.clone(),
+    }
+  }
+}
+
+/// replace `{name}` in pattern with variable `name` in one pass, so substituted values are
+/// never substituted again, unknown names are kept as is.
+fn substitute(pattern: &str, vars: &HashMap<&str, String>) -> String {
+  let mut value = String::with_capacity(pattern.len());
+  let mut rest = pattern;
+  while let Some(start) = rest.find('{') {
+    let Some(len) = rest[start..].find('}') else { break };
+    value.push_str(&rest[..start]);
+    let placeholder = &rest[start..start + len + 1];
+    match vars.get(&placeholder[1..len]) {
+      Some(var) => value.push_str(var),
+      None => value.push_str(placeholder),
+    }
+    rest = &rest[start + len + 1..];
+  }
+  value.push_str(rest);
+  value
+}
+
+/// Message templates indexed by message type, loaded from json file given by
+/// `--template-config`, e.g.
+///
+/// ```json
+/// {
+///   "paper_update": {
+///     "template_id": "TMFuXpbbjg21tEN1c4D_kHGtsNuRccqo7ft3aBC2J6s",
+///     "page": "pages/index/index",
+///     "miniprogram_state": "formal",
+///     "fields": { "thing1": "{university}", "thing2": "{department}", "time3": "{time}" }
+///   }
+/// }
+/// ```
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct TemplateConfig {
+  pub templates: HashMap<String, MessageTemplate>,
+}
+
+impl TemplateConfig {
+  pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    let config: TemplateConfig = serde_json::from_slice(&std::fs::read(path)?)?;
+    if !config.templates.contains_key(PAPER_UPDATE_TEMPLATE) {
+      return Err(format!("template {} not configured in {}", PAPER_UPDATE_TEMPLATE, path).into());
+    }
+    Ok(config)
+  }
+
+  pub fn get(&self, name: &str) -> Option<&MessageTemplate> {
+    self.templates.get(name)
+  }
+}
+
+impl Default for TemplateConfig {
+  /// the template used before templates were configurable.
+  fn default() -> Self {
+    let paper_update = MessageTemplate {
+      template_id: "TMFuXpbbjg21tEN1c4D_kHGtsNuRccqo7ft3aBC2J6s".to_string(),
+      page: None,
+      miniprogram_state: MiniProgramState::Developer,
+      lang: default_lang(),
+      fields: BTreeMap::from([
+        ("thing1".to_string(), "{university}".to_string()),
+        ("thing2".to_string(), "{department}".to_string()),
+        ("time3".to_string(), "{time}".to_string()),
+      ]),
+    };
+    TemplateConfig {
+      templates: HashMap::from([(PAPER_UPDATE_TEMPLATE.to_string(), paper_update)]),
+    }
+  }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
+use crate::types::redact;
 use crate::wechat::types::Error;
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -56,10 +57,47 @@ impl DepartmentResult {
   }
 }
 
-#[derive(Clone)]
-pub struct UniversityContext {
+/// /admin/remove receive, department is removed if department_id is given,
+/// otherwise the whole university
+#[derive(Deserialize, Serialize)]
+pub struct RemoveInfo {
+  pub admin_token: String,
   pub university_id: u32,
-  pub university_name: String,
-  pub department_id: u32,
-  pub department_name: String,
+  pub department_id: Option<u32>,
+}
+
+impl std::fmt::Debug for RemoveInfo {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("RemoveInfo")
+      .field("admin_token", &redact(&self.admin_token))
+      .field("university_id", &self.university_id)
+      .field("department_id", &self.department_id)
+      .finish()
+  }
+}
+
+/// /admin/remove return
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RemoveResult {
+  pub err_code: i32,
+  pub message: String,
+  /// number of subscriptions removed along
+  pub removed_subscriptions: u64,
+}
+
+impl RemoveResult {
+  pub fn new(arg: Result<u64, Error>) -> Self {
+    match arg {
+      Ok(removed_subscriptions) => RemoveResult {
+        err_code: Error::Success.into(),
+        message: Error::Success.into(),
+        removed_subscriptions,
+      },
+      Err(e) => RemoveResult {
+        err_code: e.into(),
+        message: e.into(),
+        removed_subscriptions: 0,
+      },
+    }
+  }
 }
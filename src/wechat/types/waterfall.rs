@@ -1,26 +1,81 @@
+use chrono::{DateTime, TimeZone, Utc};
 use crate::wechat::types::Error;
 
 use serde::{Serialize, Deserialize};
 
+/// query string of /waterfall
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct WaterFallInfo {
+  /// `next_cursor` of previous page, start from newest post if absent
+  pub cursor: Option<String>,
+  pub page_size: Option<u32>,
+  pub university_id: Option<u32>,
+  /// only meaningful together with university_id
+  pub department_id: Option<u32>,
+}
+
+impl WaterFallInfo {
+  pub const DEFAULT_PAGE_SIZE: u32 = 10;
+  pub const MAX_PAGE_SIZE: u32 = 50;
+
+  pub fn page_size(&self) -> u32 {
+    self.page_size
+      .unwrap_or(Self::DEFAULT_PAGE_SIZE)
+      .clamp(1, Self::MAX_PAGE_SIZE)
+  }
+}
+
+/// Position in waterfall, points at the last post already returned.
+/// Encoded as `{seconds}.{nanoseconds}_{id}` so posts sharing a second are not skipped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PostCursor {
+  pub publish_date: DateTime<Utc>,
+  pub id: u32,
+}
+
+impl PostCursor {
+  pub fn encode(&self) -> String {
+    format!("{}.{:09}_{}", self.publish_date.timestamp(), self.publish_date.timestamp_subsec_nanos(), self.id)
+  }
+
+  /// also accepts cursors without nanoseconds issued before they were encoded.
+  pub fn parse(cursor: &str) -> Option<Self> {
+    let (timestamp, id) = cursor.split_once('_')?;
+    let (secs, nanos) = match timestamp.split_once('.') {
+      Some((secs, nanos)) if nanos.len() == 9 => (secs, nanos.parse().ok()?),
+      Some(_) => return None,
+      None => (timestamp, 0),
+    };
+    Some(PostCursor {
+      publish_date: Utc.timestamp_opt(secs.parse().ok()?, nanos).single()?,
+      id: id.parse().ok()?,
+    })
+  }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct WaterFall {
   pub err_code: i32,
   pub message: String,
   pub items: Vec<WaterFallItem>,
+  /// cursor for next page, empty if there is no more post
+  pub next_cursor: String,
 }
 
 impl WaterFall {
-  pub fn new(arg: Result<Vec<WaterFallItem>, Error>) -> Self {
+  pub fn new(arg: Result<(Vec<WaterFallItem>, Option<PostCursor>), Error>) -> Self {
     match arg {
-      Ok(items) => WaterFall {
+      Ok((items, next_cursor)) => WaterFall {
         err_code: 0,
         message: "".to_string(),
         items,
+        next_cursor: next_cursor.map_or_else(String::new, |c| c.encode()),
       },
       Err(e) => WaterFall {
         err_code: e.into(),
         message: e.into(),
         items: Vec::new(),
+        next_cursor: "".to_string(),
       },
     }
   }
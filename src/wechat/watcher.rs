@@ -0,0 +1,135 @@
+//! background task watching assets_path/paper for new papers
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::database::{ProspectSqlPool, ProspectStore};
+use super::outbox::notify_department;
+use super::types::Context;
+
+/// files of each department, indexed by university name then department name.
+type PaperTree = BTreeMap<String, BTreeMap<String, BTreeSet<String>>>;
+
+/// Watch assets_path/paper every interval, register new universities and departments,
+/// and notify subscribers of a department when new papers appear in it.
+pub async fn watch_papers(ctx: Context, interval: Duration) {
+  let root = Path::new(&ctx.options.assets_path).join("paper");
+  let mut known = seed(&ctx, scan(root.clone()).await).await;
+  info!("watching {} for new papers every {:?}", root.display(), interval);
+  let mut ticker = tokio::time::interval(interval);
+  // first tick completes immediately
+  ticker.tick().await;
+  loop {
+    ticker.tick().await;
+    let current = scan(root.clone()).await;
+    for (university, departments) in current {
+      if !known.contains_key(&university) {
+        match ctx.store.add_university(&ProspectSqlPool::name_hash(&university), &university).await {
+          Ok(_) => info!("new university {} registered", university),
+          Err(e) => {
+            // leave it unknown to retry on next tick
+            warn!("register {} failed: {:?}", university, e);
+            continue;
+          }
+        }
+      }
+      let known_departments = known.entry(university.clone()).or_default();
+      for (department, papers) in departments {
+        let is_new_department = !known_departments.contains_key(&department);
+        let new_papers = known_departments
+          .get(&department)
+          .map_or_else(Vec::new, |known_papers| papers.difference(known_papers).cloned().collect());
+        if !is_new_department && new_papers.is_empty() {
+          continue;
+        }
+        let (university_id, department_id) = match register(ctx.store.as_ref(), &university, &department).await {
+          Ok(ids) => ids,
+          Err(e) => {
+            warn!("register {}/{} failed: {:?}", university, department, e);
+            continue;
+          }
+        };
+        if is_new_department {
+          info!("new department {}/{} registered", university, department);
+        } else {
+          info!("new papers {:?} in {}/{}", new_papers, university, department);
+          match notify_department(&ctx, university_id, department_id).await {
+            Ok(report) => info!(
+              "notified {} users of {}/{}, {} failed",
+              report.succeeded.len(), university, department, report.failed.len(),
+            ),
+            Err(e) => {
+              warn!("notify subscribers of {}/{} failed: {:?}", university, department, e);
+              continue;
+            }
+          }
+        }
+        known_departments.insert(department, papers);
+      }
+    }
+  }
+}
+
+/// register everything found by the first scan without notifying anyone,
+/// return what got registered, the rest is picked up as new on next tick.
+async fn seed(ctx: &Context, tree: PaperTree) -> PaperTree {
+  let mut known = PaperTree::new();
+  for (university, departments) in tree {
+    if let Err(e) = ctx.store.add_university(&ProspectSqlPool::name_hash(&university), &university).await {
+      warn!("register {} failed: {:?}", university, e);
+      continue;
+    }
+    let known_departments = known.entry(university.clone()).or_default();
+    for (department, papers) in departments {
+      match register(ctx.store.as_ref(), &university, &department).await {
+        Ok(_) => {
+          known_departments.insert(department, papers);
+        }
+        Err(e) => warn!("register {}/{} failed: {:?}", university, department, e),
+      }
+    }
+  }
+  known
+}
+
+/// register university and department the same way as init_from_assets, return their ids.
+async fn register(store: &dyn ProspectStore, university: &str, department: &str) -> Result<(u32, u32), sqlx::Error> {
+  let university_id = store.add_university(&ProspectSqlPool::name_hash(university), university).await?;
+  let department_id = store.add_department(
+    university_id,
+    &ProspectSqlPool::name_hash(&(university.to_string() + department)),
+    department,
+  ).await?;
+  Ok((university_id, department_id))
+}
+
+async fn scan(root: PathBuf) -> PaperTree {
+  tokio::task::spawn_blocking(move || {
+    let mut tree = PaperTree::new();
+    for university in entries(&root, true) {
+      let departments = tree.entry(university.clone()).or_default();
+      for department in entries(&root.join(&university), true) {
+        let papers = entries(&root.join(&university).join(&department), false);
+        departments.insert(department, papers.into_iter().collect());
+      }
+    }
+    tree
+  }).await.unwrap_or_default()
+}
+
+/// names of visible directories or files directly under path.
+fn entries(path: &Path, dir: bool) -> Vec<String> {
+  std::fs::read_dir(path)
+    .map(|rd| {
+      rd
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|tp| if dir { tp.is_dir() } else { tp.is_file() }))
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect()
+    })
+    .unwrap_or_default()
+}
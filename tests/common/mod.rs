@@ -0,0 +1,138 @@
+//! helpers shared by handler tests
+
+#![allow(dead_code)]
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+
+use argh::FromArgs;
+use chrono::{Duration, TimeZone, Utc};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use warp::{Filter, Reply};
+
+use prospect_backend::database::MemoryStore;
+use prospect_backend::wechat::auth::*;
+use prospect_backend::wechat::handlers::waterfall_handler;
+use prospect_backend::wechat::mock::*;
+use prospect_backend::wechat::types::*;
+
+/// database file under temp dir, removed when dropped
+pub struct TempDb(std::path::PathBuf);
+
+impl TempDb {
+  pub fn new() -> Self {
+    let name = format!("prospect_{:016x}.db", rand::thread_rng().gen::<u64>());
+    TempDb(std::env::temp_dir().join(name))
+  }
+
+  pub fn url(&self) -> String {
+    format!("sqlite://{}", self.0.display())
+  }
+}
+
+impl Drop for TempDb {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.0);
+  }
+}
+
+pub const ADMIN_TOKEN: &str = "admin";
+
+/// options of serve_wx with required ones filled, followed by extra arguments.
+pub fn options(extra: &[&str]) -> Options {
+  let mut args = vec![
+    "127.0.0.1:0",
+    "-c", "cert", "-k", "key",
+    "-u", "user", "-a", "127.0.0.1:1", "-p", "passwd",
+    "-i", MOCK_APP_ID, "-s", MOCK_APP_SECRET,
+    "-x", "assets",
+    "--admin-token", ADMIN_TOKEN,
+  ];
+  args.extend_from_slice(extra);
+  Options::from_args(&["serve_wx"], &args).unwrap()
+}
+
+/// context backed by an in-memory store, talking to mock wechat server.
+pub fn context(server: &MockWechatServer) -> Context {
+  context_with(server, options(&[]))
+}
+
+pub fn context_with(server: &MockWechatServer, options: Options) -> Context {
+  context_with_store(server, options, Arc::new(MemoryStore::new()))
+}
+
+pub fn context_with_store(server: &MockWechatServer, options: Options, store: PPool) -> Context {
+  Context::new(store, Arc::new(options), Arc::new(TemplateConfig::default()))
+    .with_wechat_api(Arc::new(server.api()))
+}
+
+pub fn with_context(ctx: Context) -> impl Filter<Extract=(Context, ), Error=Infallible> + Clone {
+  warp::any().map(move || ctx.clone())
+}
+
+/// post json body with headers to handler behind with_wechat_user, return reply body.
+pub async fn post_as_user<T, F, Fut, R>(ctx: &Context, handler: F, headers: &[(&str, &str)], body: &impl Serialize) -> Value
+where
+  T: DeserializeOwned + Send + 'static,
+  F: Fn(WechatUser, T, Context) -> Fut + Clone + Send + Sync + 'static,
+  Fut: Future<Output=Result<R, Infallible>> + Send,
+  R: Reply,
+{
+  let route = with_wechat_user::<T>(ctx.clone())
+    .and(with_context(ctx.clone()))
+    .and_then(handler)
+    .recover(handle_rejection);
+  let mut request = warp::test::request().method("POST").json(body);
+  for (name, value) in headers {
+    request = request.header(*name, *value);
+  }
+  let reply = request.reply(&route).await;
+  assert_eq!(reply.status(), 200);
+  serde_json::from_slice(reply.body()).unwrap()
+}
+
+/// add posts published within one second, then page through waterfall two at a time,
+/// every post should come out exactly once, newest first.
+pub async fn page_through_same_second(ctx: &Context) {
+  let second = Utc.timestamp_opt(1_709_280_000, 0).unwrap();
+  let offsets = [0, 100, 100, 250, 900];
+  for (i, millis) in offsets.iter().enumerate() {
+    ctx.store.add_post(&PostRecord {
+      title: format!("post {}", i),
+      img_source_link: "".into(),
+      asset_path: format!("post/{}.md", i),
+      author: "author".into(),
+      publish_date: second + Duration::milliseconds(*millis),
+      status: PostStatus::Published,
+      university_id: None,
+      department_id: None,
+    }).await.unwrap();
+  }
+
+  let mut titles = Vec::new();
+  let mut cursor = None;
+  loop {
+    let info = WaterFallInfo { cursor, page_size: Some(2), ..Default::default() };
+    let reply = body(waterfall_handler(info, ctx.clone()).await.unwrap()).await;
+    assert_eq!(reply["err_code"], 0);
+    titles.extend(reply["items"].as_array().unwrap().iter().map(|item| item["title"].as_str().unwrap().to_string()));
+    match reply["next_cursor"].as_str().unwrap() {
+      "" => break,
+      next => cursor = Some(next.to_string()),
+    }
+  }
+  assert_eq!(titles, ["post 4", "post 3", "post 2", "post 1", "post 0"]);
+}
+
+pub async fn body(reply: impl Reply) -> Value {
+  let body = warp::hyper::body::to_bytes(reply.into_response().into_body()).await.unwrap();
+  serde_json::from_slice(&body).unwrap()
+}
+
+pub async fn err_code(reply: impl Reply) -> i32 {
+  body(reply).await["err_code"].as_i64().unwrap() as i32
+}
@@ -0,0 +1,482 @@
+use warp::Filter;
+
+use prospect_backend::types::{LogInInfo, SignUpInfo};
+use prospect_backend::wechat::auth::*;
+use prospect_backend::wechat::handlers::*;
+use prospect_backend::wechat::mock::*;
+use prospect_backend::wechat::types::*;
+
+mod common;
+use common::*;
+
+/// log in through send_code, return open_id and access token.
+async fn log_in(ctx: &Context, code: &str) -> (String, String) {
+  log_in_from(ctx, code, "").await
+}
+
+/// log in through send_code from a named device.
+async fn log_in_from(ctx: &Context, code: &str, device: &str) -> (String, String) {
+  let info = CodeInfo { code: code.into(), open_id: "".into(), access_token: "".into(), device: device.into() };
+  let reply = body(send_code_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["err_code"], 0);
+  (reply["open_id"].as_str().unwrap().into(), reply["access_token"].as_str().unwrap().into())
+}
+
+/// university with one department, return their ids.
+async fn university(ctx: &Context) -> (u32, u32) {
+  let university_id = ctx.store.add_university("u", "university").await.unwrap();
+  let department_id = ctx.store.add_department(university_id, "d", "department").await.unwrap();
+  (university_id, department_id)
+}
+
+fn subscribe_info(open_id: &str, access_token: &str, university_id: u32, department_id: u32, oper: u16) -> SubscribeInfo {
+  SubscribeInfo {
+    open_id: open_id.into(),
+    access_token: access_token.into(),
+    info: vec![SubscribeDetail { school_code: university_id, department_code: department_id, oper }],
+  }
+}
+
+#[tokio::test]
+async fn send_code_issues_token() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  assert_eq!(open_id, "mock_open_id_abc");
+  assert!(ctx.store.is_valid_access_token(&open_id, access_token.clone().into()).await.unwrap());
+  // tokens are random, not derived from open_id and time
+  let (_, again) = log_in(&ctx, "abc").await;
+  assert_ne!(again, access_token);
+  // each login is a session of its own
+  assert!(ctx.store.is_valid_access_token(&open_id, access_token.clone().into()).await.unwrap());
+  assert!(ctx.store.is_valid_access_token(&open_id, again.into()).await.unwrap());
+
+  // token is accepted when presented again
+  let info = CodeInfo { code: "".into(), open_id: open_id.clone(), access_token, device: "".into() };
+  let reply = body(send_code_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["open_id"], open_id.as_str());
+
+  let info = CodeInfo { code: "".into(), open_id, access_token: "wrong".into(), device: "".into() };
+  let expired: i32 = Error::TokenExpired.into();
+  assert_eq!(err_code(send_code_handler(info, ctx).await.unwrap()).await, expired);
+}
+
+/// present token to send_code again, return reply.
+async fn renew(ctx: &Context, open_id: &str, access_token: &str) -> serde_json::Value {
+  let info = CodeInfo { code: "".into(), open_id: open_id.into(), access_token: access_token.into(), device: "".into() };
+  body(send_code_handler(info, ctx.clone()).await.unwrap()).await
+}
+
+#[tokio::test]
+async fn token_rotation_and_grace() {
+  let server = MockWechatServer::start().await;
+
+  // fresh tokens are kept
+  let ctx = context(&server);
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  let reply = renew(&ctx, &open_id, &access_token).await;
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["access_token"], access_token.as_str());
+
+  // replaced token is still accepted within grace, so requests sent in parallel succeed
+  let ctx = context_with(&server, options(&["--token-rotate-after", "0"]));
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  let reply = renew(&ctx, &open_id, &access_token).await;
+  let rotated = reply["access_token"].as_str().unwrap().to_string();
+  let rotated_expired = reply["expired"].as_i64().unwrap();
+  assert_ne!(rotated, access_token);
+  let reply = renew(&ctx, &open_id, &access_token).await;
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["access_token"], access_token.as_str());
+  // replaced token expires at end of grace, before the token replacing it
+  let now = chrono::Utc::now().timestamp();
+  let grace_expired = reply["expired"].as_i64().unwrap();
+  assert!(now <= grace_expired && grace_expired <= now + 30, "{} not within grace of {}", grace_expired, now);
+  assert!(grace_expired < rotated_expired);
+  assert!(ctx.store.is_valid_access_token(&open_id, access_token.clone().into()).await.unwrap());
+  assert!(ctx.store.is_valid_access_token(&open_id, rotated.clone().into()).await.unwrap());
+  // both are one session
+  assert_eq!(ctx.store.get_sessions(&open_id).await.unwrap().len(), 1);
+
+  // and rejected without grace
+  let ctx = context_with(&server, options(&["--token-rotate-after", "0", "--token-grace", "0"]));
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  let reply = renew(&ctx, &open_id, &access_token).await;
+  assert_eq!(reply["err_code"], 0);
+  let expired: i32 = Error::TokenExpired.into();
+  assert_eq!(renew(&ctx, &open_id, &access_token).await["err_code"], expired);
+  assert!(!ctx.store.is_valid_access_token(&open_id, access_token.into()).await.unwrap());
+}
+
+#[tokio::test]
+async fn list_and_revoke_sessions() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (open_id, phone) = log_in_from(&ctx, "abc", "phone").await;
+  let (_, laptop) = log_in_from(&ctx, "abc", "laptop").await;
+  let (_, tablet) = log_in_from(&ctx, "abc", "tablet").await;
+
+  let info = SessionsInfo { open_id: open_id.clone(), access_token: phone.clone() };
+  let reply = post_as_user(&ctx, sessions_handler, &[], &info).await;
+  assert_eq!(reply["err_code"], 0);
+  let sessions = reply["sessions"].as_array().unwrap();
+  assert_eq!(sessions.len(), 3);
+  let current = sessions.iter().find(|s| s["id"] == reply["current"]).unwrap();
+  assert_eq!(current["device"], "phone");
+  let laptop_id = sessions.iter().find(|s| s["device"] == "laptop").unwrap()["id"].as_u64().unwrap();
+
+  // revoke one session
+  let info = RevokeSessionInfo { open_id: open_id.clone(), access_token: phone.clone(), session_id: Some(laptop_id) };
+  let reply = post_as_user(&ctx, revoke_session_handler, &[], &info).await;
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["revoked"], 1);
+  assert!(!ctx.store.is_valid_access_token(&open_id, laptop.clone().into()).await.unwrap());
+  assert!(ctx.store.is_valid_access_token(&open_id, tablet.clone().into()).await.unwrap());
+
+  let info = RevokeSessionInfo { open_id: open_id.clone(), access_token: phone.clone(), session_id: Some(laptop_id) };
+  let invalid: i32 = Error::InvalidJsonRequest.into();
+  assert_eq!(post_as_user(&ctx, revoke_session_handler, &[], &info).await["err_code"], invalid);
+
+  // sessions of other users can not be revoked
+  let (other, other_token) = log_in_from(&ctx, "def", "phone").await;
+  let info = SessionsInfo { open_id: other.clone(), access_token: other_token.clone() };
+  let reply = post_as_user(&ctx, sessions_handler, &[], &info).await;
+  let other_id = reply["current"].as_u64().unwrap();
+  let info = RevokeSessionInfo { open_id: open_id.clone(), access_token: phone.clone(), session_id: Some(other_id) };
+  assert_eq!(post_as_user(&ctx, revoke_session_handler, &[], &info).await["err_code"], invalid);
+  assert!(ctx.store.is_valid_access_token(&other, other_token.into()).await.unwrap());
+
+  // revoke all sessions
+  let info = RevokeSessionInfo { open_id: open_id.clone(), access_token: tablet.clone(), session_id: None };
+  let reply = post_as_user(&ctx, revoke_session_handler, &[], &info).await;
+  assert_eq!(reply["revoked"], 2);
+  assert!(!ctx.store.is_valid_access_token(&open_id, phone.clone().into()).await.unwrap());
+
+  let info = SessionsInfo { open_id, access_token: phone };
+  let expired: i32 = Error::TokenExpired.into();
+  assert_eq!(post_as_user(&ctx, sessions_handler, &[], &info).await["err_code"], expired);
+}
+
+#[tokio::test]
+async fn subscribe_and_get_subscribe() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (university_id, department_id) = university(&ctx).await;
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+
+  let info = subscribe_info(&open_id, &access_token, university_id, department_id, 0);
+  assert_eq!(post_as_user(&ctx, subscribe_handler, &[], &info).await["err_code"], 0);
+  let info = GetSubscribeInfo { open_id: open_id.clone(), access_token: access_token.clone() };
+  let reply = post_as_user(&ctx, get_user_subscribe_handler, &[], &info).await;
+  assert_eq!(reply["info"][university_id.to_string()][0], department_id);
+
+  let info = subscribe_info(&open_id, &access_token, university_id, department_id, 1);
+  assert_eq!(post_as_user(&ctx, subscribe_handler, &[], &info).await["err_code"], 0);
+  assert!(ctx.store.get_subscriptions(&open_id).await.unwrap().is_empty());
+
+  let info = subscribe_info(&open_id, "wrong", university_id, department_id, 0);
+  let expired: i32 = Error::TokenExpired.into();
+  assert_eq!(post_as_user(&ctx, subscribe_handler, &[], &info).await["err_code"], expired);
+}
+
+#[tokio::test]
+async fn wechat_user_from_headers() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (university_id, department_id) = university(&ctx).await;
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  let headers = [(OPEN_ID_HEADER, open_id.as_str()), (ACCESS_TOKEN_HEADER, access_token.as_str())];
+
+  let info = serde_json::json!({ "info": [{ "school_code": university_id, "department_code": department_id, "oper": 0 }] });
+  assert_eq!(post_as_user(&ctx, subscribe_handler, &headers, &info).await["err_code"], 0);
+  let reply = post_as_user(&ctx, get_user_subscribe_handler, &headers, &serde_json::json!({})).await;
+  assert_eq!(reply["info"][university_id.to_string()][0], department_id);
+
+  // headers take place of fields in body
+  let info = GetSubscribeInfo { open_id: "other".into(), access_token: "wrong".into() };
+  assert_eq!(post_as_user(&ctx, get_user_subscribe_handler, &headers, &info).await["err_code"], 0);
+
+  // rejections are replied alike for every handler
+  let expired: i32 = Error::TokenExpired.into();
+  let headers = [(OPEN_ID_HEADER, open_id.as_str()), (ACCESS_TOKEN_HEADER, "wrong")];
+  let reply = post_as_user(&ctx, get_user_subscribe_handler, &headers, &serde_json::json!({})).await;
+  assert_eq!(reply["err_code"], expired);
+  assert_eq!(reply["message"], String::from(Error::TokenExpired));
+  let invalid: i32 = Error::InvalidJsonRequest.into();
+  let info = serde_json::json!({ "open_id": open_id, "access_token": access_token });
+  assert_eq!(post_as_user(&ctx, subscribe_handler, &[], &info).await["err_code"], invalid);
+}
+
+#[tokio::test]
+async fn get_university_and_department() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (university_id, department_id) = university(&ctx).await;
+
+  let reply = body(get_university_handler(ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["universities"][university_id.to_string()], "university");
+  let info = GetDepartmentInfo { university_code: university_id };
+  let reply = body(get_department_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["departments"][department_id.to_string()], "department");
+
+  let info = GetDepartmentInfo { university_code: university_id + 1 };
+  let invalid: i32 = Error::InvalidJsonRequest.into();
+  assert_eq!(err_code(get_department_handler(info, ctx).await.unwrap()).await, invalid);
+}
+
+#[tokio::test]
+async fn init_from_assets_skips_missing_dirs() {
+  use std::os::unix::ffi::OsStrExt;
+
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let assets = std::env::temp_dir().join(format!("prospect_assets_{}", std::process::id()));
+  std::fs::create_dir_all(assets.join("paper/university/department")).unwrap();
+  // names that are not utf-8 are skipped too
+  std::fs::create_dir_all(assets.join("paper").join(std::ffi::OsStr::from_bytes(b"\xff"))).unwrap();
+  std::fs::create_dir_all(assets.join("paper/university").join(std::ffi::OsStr::from_bytes(b"\xfe"))).unwrap();
+
+  // no assets/post at all
+  let result = ctx.store.init_from_assets(assets.display().to_string()).await;
+  std::fs::remove_dir_all(&assets).unwrap();
+  result.unwrap();
+  let reply = body(get_university_handler(ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["universities"].as_object().unwrap().len(), 1);
+  let university_id = reply["universities"].as_object().unwrap().keys().next().unwrap().parse().unwrap();
+  assert_eq!(ctx.store.get_departments(university_id).await.unwrap().len(), 1);
+
+  ctx.store.init_from_assets(assets.display().to_string()).await.unwrap();
+}
+
+#[tokio::test]
+async fn waterfall_pages_posts_within_one_second() {
+  let server = MockWechatServer::start().await;
+  page_through_same_second(&context(&server)).await;
+}
+
+#[tokio::test]
+async fn post_folders_only_tag_posts() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let assets = std::env::temp_dir().join(format!("prospect_posts_{}", std::process::id()));
+  std::fs::create_dir_all(assets.join("paper/university/department")).unwrap();
+  for dir in ["post/university/department", "post/university/other", "post/extra"] {
+    std::fs::create_dir_all(assets.join(dir)).unwrap();
+    std::fs::write(assets.join(dir).join("post.md"), "# post").unwrap();
+  }
+
+  let result = ctx.store.init_from_assets(assets.display().to_string()).await;
+  std::fs::remove_dir_all(&assets).unwrap();
+  result.unwrap();
+  let universities = ctx.store.get_universities().await.unwrap();
+  assert_eq!(universities.values().collect::<Vec<_>>(), ["university"]);
+  let (&university_id, _) = universities.iter().next().unwrap();
+  let departments = ctx.store.get_departments(university_id).await.unwrap();
+  assert_eq!(departments.values().collect::<Vec<_>>(), ["department"]);
+  let (&department_id, _) = departments.iter().next().unwrap();
+
+  let info = WaterFallInfo::default();
+  let reply = body(waterfall_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["items"].as_array().unwrap().len(), 1);
+  assert_eq!(reply["items"][0]["post_id"], "post/university/department/post.md");
+  let info = WaterFallInfo { university_id: Some(university_id), department_id: Some(department_id), ..Default::default() };
+  let reply = body(waterfall_handler(info, ctx).await.unwrap()).await;
+  assert_eq!(reply["items"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn watcher_registers_papers_found_at_startup() {
+  let server = MockWechatServer::start().await;
+  let assets = std::env::temp_dir().join(format!("prospect_watch_{}", std::process::id()));
+  std::fs::create_dir_all(assets.join("paper/university/department")).unwrap();
+  std::fs::write(assets.join("paper/university/department/paper.pdf"), "").unwrap();
+  let mut options = options(&[]);
+  options.assets_path = assets.display().to_string();
+  let ctx = context_with(&server, options);
+
+  let watcher = tokio::spawn(prospect_backend::wechat::watcher::watch_papers(ctx.clone(), std::time::Duration::from_secs(3600)));
+  let mut departments = Default::default();
+  for _ in 0..50 {
+    let universities = ctx.store.get_universities().await.unwrap();
+    if let Some(university_id) = universities.keys().next() {
+      departments = ctx.store.get_departments(*university_id).await.unwrap();
+      if !departments.is_empty() {
+        break;
+      }
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+  }
+  watcher.abort();
+  std::fs::remove_dir_all(&assets).unwrap();
+  assert_eq!(departments.into_values().collect::<Vec<_>>(), ["department"]);
+  assert!(server.requests(MockEndpoint::SendSubscribeMessage).is_empty());
+}
+
+#[tokio::test]
+async fn source_content_is_base64() {
+  let server = MockWechatServer::start().await;
+  let assets = std::env::temp_dir().join(format!("prospect_source_{}", std::process::id()));
+  std::fs::create_dir_all(assets.join("paper/university/department")).unwrap();
+  let paper = b"%PDF-1.4\n\x00\xff";
+  std::fs::write(assets.join("paper/university/department/paper.pdf"), paper).unwrap();
+  let mut options = options(&[]);
+  options.assets_path = assets.display().to_string();
+  let ctx = context_with(&server, options);
+  let (university_id, department_id) = university(&ctx).await;
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+
+  let info = SourceInfo { open_id, access_token, university_id, department_id, paper: "paper.pdf".into() };
+  let reply = post_as_user(&ctx, source_handler, &[], &info).await;
+  std::fs::remove_dir_all(&assets).unwrap();
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["content"], base64::encode(paper));
+  let content: SourceContent = serde_json::from_value(reply).unwrap();
+  assert_eq!(content.content, paper);
+}
+
+#[tokio::test]
+async fn notify_delivers_and_unsubscribes() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (university_id, department_id) = university(&ctx).await;
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  let info = subscribe_info(&open_id, &access_token, university_id, department_id, 0);
+  post_as_user(&ctx, subscribe_handler, &[], &info).await;
+
+  let info = NotifyInfo { admin_token: "wrong".into(), university_id, department_id };
+  let denied: i32 = Error::PermissionDenied.into();
+  assert_eq!(err_code(notify_subscription(info, ctx.clone()).await.unwrap()).await, denied);
+
+  let info = NotifyInfo { admin_token: ADMIN_TOKEN.into(), university_id, department_id };
+  let reply = body(notify_subscription(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["succeeded"][0], open_id.as_str());
+
+  let requests = server.requests(MockEndpoint::SendSubscribeMessage);
+  assert_eq!(requests.len(), 1);
+  assert_eq!(requests[0].body.as_ref().unwrap()["touser"], open_id.as_str());
+  assert!(ctx.store.get_subscriptions(&open_id).await.unwrap().is_empty());
+  let history = ctx.store.outbox_history(&open_id).await.unwrap();
+  assert!(history[0].delivered_at.is_some());
+}
+
+#[test]
+fn options_debug_redacts_secrets() {
+  let printed = format!("{:?}", options(&[]));
+  assert!(printed.contains(MOCK_APP_ID));
+  for secret in [MOCK_APP_SECRET, "\"passwd\"", "\"admin\""] {
+    assert!(!printed.contains(secret), "{} in {}", secret, printed);
+  }
+
+  // nor does context talking to wechat over http
+  let store = std::sync::Arc::new(prospect_backend::database::MemoryStore::new());
+  let ctx = Context::new(store, std::sync::Arc::new(options(&[])), Default::default());
+  let printed = format!("{:?}", ctx);
+  assert!(printed.contains("HttpWechatApi"));
+  assert!(!printed.contains(MOCK_APP_SECRET), "{} in {}", MOCK_APP_SECRET, printed);
+}
+
+#[test]
+fn request_debug_redacts_tokens() {
+  let code = CodeInfo { code: "wx-code".into(), open_id: "user".into(), access_token: "token".into(), device: "".into() };
+  let token = AccessToken::new();
+  let session = Code2SessionResponse { openid: Some("user".into()), session_key: Some("key".into()), errcode: None, errmsg: None };
+  let log_in = LogInInfo { username: "alice".into(), password: "secret".into() };
+  let printed = format!("{:?} {:?} {:?} {:?}", code, token, session, log_in);
+  assert!(printed.contains("\"user\"") && printed.contains("\"alice\""));
+  for secret in ["wx-code", "\"token\"", &token.token, "\"key\"", "secret"] {
+    assert!(!printed.contains(secret), "{} in {}", secret, printed);
+  }
+}
+
+#[tokio::test]
+async fn remove_department_and_university() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let (university_id, department_id) = university(&ctx).await;
+  let (open_id, access_token) = log_in(&ctx, "abc").await;
+  let info = subscribe_info(&open_id, &access_token, university_id, department_id, 0);
+  post_as_user(&ctx, subscribe_handler, &[], &info).await;
+
+  let info = RemoveInfo { admin_token: ADMIN_TOKEN.into(), university_id, department_id: Some(department_id) };
+  let reply = body(remove_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["err_code"], 0);
+  assert_eq!(reply["removed_subscriptions"], 1);
+  assert!(ctx.store.get_subscriptions(&open_id).await.unwrap().is_empty());
+
+  let info = RemoveInfo { admin_token: ADMIN_TOKEN.into(), university_id, department_id: Some(department_id) };
+  let invalid: i32 = Error::InvalidJsonRequest.into();
+  assert_eq!(err_code(remove_handler(info, ctx.clone()).await.unwrap()).await, invalid);
+
+  let info = RemoveInfo { admin_token: ADMIN_TOKEN.into(), university_id, department_id: None };
+  assert_eq!(err_code(remove_handler(info, ctx.clone()).await.unwrap()).await, 0);
+  assert!(ctx.store.get_universities().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn sign_up_and_log_in() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let sign_up = |username: &str, password: &str| SignUpInfo { username: username.into(), password: password.into() };
+  let log_in = |username: &str, password: &str| LogInInfo { username: username.into(), password: password.into() };
+
+  let reply = body(sign_up_handler(sign_up("alice", "secret"), ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["success"], true);
+  let reply = body(sign_up_handler(sign_up("alice", "other"), ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["success"], false);
+  let reply = body(sign_up_handler(sign_up("", "secret"), ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["success"], false);
+
+  let reply = body(log_in_handler(log_in("alice", "secret"), ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["success"], true);
+  assert_eq!(reply["access_token"].as_str().unwrap().len(), 64);
+  let first = reply["access_token"].clone();
+  let reply = body(log_in_handler(log_in("alice", "secret"), ctx.clone()).await.unwrap()).await;
+  assert_ne!(reply["access_token"], first);
+
+  let reply = body(log_in_handler(log_in("alice", "wrong"), ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["success"], false);
+  assert_eq!(reply["access_token"], "");
+  let wrong_password = reply["message"].clone();
+  let reply = body(log_in_handler(log_in("bob", "secret"), ctx).await.unwrap()).await;
+  assert_eq!(reply["success"], false);
+  // unknown user cannot be told from wrong password
+  assert_eq!(reply["message"], wrong_password);
+}
+
+#[tokio::test]
+async fn web_session_and_log_out() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let info = SignUpInfo { username: "alice".into(), password: "secret".into() };
+  ctx.store.sign_up(info).await.unwrap();
+  let info = LogInInfo { username: "alice".into(), password: "secret".into() };
+  let (user_id, token) = ctx.store.log_in(info).await.unwrap();
+  let token = String::from(token);
+  assert_eq!(ctx.store.web_session_user(&token).await.unwrap(), user_id);
+
+  let route = warp::path("logout")
+    .and(with_web_user(ctx.clone()))
+    .and(with_context(ctx.clone()))
+    .and_then(log_out_handler)
+    .recover(handle_rejection);
+  let log_out = |authorization: &str| {
+    warp::test::request().method("POST").path("/logout").header("authorization", authorization)
+  };
+
+  let reply = log_out("Bearer wrong").reply(&route).await;
+  assert_eq!(reply.status(), 401);
+  let reply = warp::test::request().method("POST").path("/logout").reply(&route).await;
+  assert_eq!(reply.status(), 401);
+
+  let reply = log_out(&format!("Bearer {}", token)).reply(&route).await;
+  assert_eq!(reply.status(), 200);
+  let reply: serde_json::Value = serde_json::from_slice(reply.body()).unwrap();
+  assert_eq!(reply["success"], true);
+
+  // session is revoked
+  assert!(ctx.store.web_session_user(&token).await.is_err());
+  let reply = log_out(&format!("Bearer {}", token)).reply(&route).await;
+  assert_eq!(reply.status(), 401);
+}
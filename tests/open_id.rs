@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use prospect_backend::database::{open_store, MemoryStore, ProspectStore};
+
+use prospect_backend::wechat::handlers::*;
+use prospect_backend::wechat::mock::*;
+use prospect_backend::wechat::types::*;
+
+mod common;
+use common::*;
+
+const PAYLOADS: &[&str] = &[
+  "",
+  "x; DROP TABLE Prospect.tokenMap; --",
+  "x` (id INT); DROP DATABASE Prospect; --",
+  "' OR '1'='1",
+  "x\" OR \"1\"=\"1",
+  "x/*comment*/",
+  "../../etc/passwd",
+  "x y",
+  "x\0y",
+  "x\ny",
+  "ｘ",
+];
+
+#[test]
+fn open_id_format() {
+  assert!(is_valid_open_id("oGZUI0egBJY1zhBYw2KhdUfwVJJE"));
+  assert!(is_valid_open_id("mock_open_id_abc-1"));
+  assert!(!is_valid_open_id(&"a".repeat(MAX_OPEN_ID_LEN + 1)));
+  for payload in PAYLOADS {
+    assert!(!is_valid_open_id(payload), "{:?} accepted", payload);
+  }
+}
+
+#[tokio::test]
+async fn handlers_reject_payloads() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let invalid: i32 = Error::InvalidOpenId.into();
+  for payload in PAYLOADS {
+    let info = SubscribeInfo {
+      open_id: payload.to_string(),
+      access_token: "token".into(),
+      info: vec![SubscribeDetail { school_code: 1, department_code: 1, oper: 0 }],
+    };
+    assert_eq!(post_as_user(&ctx, subscribe_handler, &[], &info).await["err_code"], invalid);
+
+    let info = GetSubscribeInfo { open_id: payload.to_string(), access_token: "token".into() };
+    assert_eq!(post_as_user(&ctx, get_user_subscribe_handler, &[], &info).await["err_code"], invalid);
+
+    let info = SourceInfo {
+      open_id: payload.to_string(),
+      access_token: "token".into(),
+      university_id: 1,
+      department_id: 1,
+      paper: "paper.pdf".into(),
+    };
+    assert_eq!(post_as_user(&ctx, source_handler, &[], &info).await["err_code"], invalid);
+
+    let info = CodeInfo { code: "".into(), open_id: payload.to_string(), access_token: "token".into(), device: "".into() };
+    assert_eq!(err_code(send_code_handler(info, ctx.clone()).await.unwrap()).await, invalid);
+  }
+}
+
+#[tokio::test]
+async fn valid_open_id_is_accepted() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  let info = GetSubscribeInfo { open_id: "oGZUI0egBJY1zhBYw2KhdUfwVJJE".into(), access_token: "token".into() };
+  let expired: i32 = Error::TokenExpired.into();
+  assert_eq!(post_as_user(&ctx, get_user_subscribe_handler, &[], &info).await["err_code"], expired);
+}
+
+#[tokio::test]
+async fn open_id_from_wechat_is_checked() {
+  let server = MockWechatServer::start().await;
+  let ctx = context(&server);
+  server.push(MockEndpoint::Code2Session, MockReply::Json(json!({
+    "openid": "x; DROP TABLE Prospect.tokenMap; --",
+    "session_key": "key",
+  })));
+  let info = CodeInfo { code: "abc".into(), open_id: "".into(), access_token: "".into(), device: "".into() };
+  let invalid: i32 = Error::InvalidOpenId.into();
+  assert_eq!(err_code(send_code_handler(info, ctx).await.unwrap()).await, invalid);
+}
+
+/// payloads reaching store as open_id, name and hash are stored and read back as is.
+async fn store_keeps_payloads_literally(store: &dyn ProspectStore) {
+  let mut ids = Vec::new();
+  for payload in PAYLOADS {
+    let university_id = store.add_university(payload, payload).await.unwrap();
+    let department_id = store.add_department(university_id, &format!("d{}", payload), payload).await.unwrap();
+    store.subscribe_user(SubscribeInfo {
+      open_id: payload.to_string(),
+      access_token: "".into(),
+      info: vec![SubscribeDetail { school_code: university_id, department_code: department_id, oper: 0 }],
+    }).await.unwrap();
+    ids.push((university_id, department_id));
+  }
+
+  let universities = store.get_universities().await.unwrap();
+  assert_eq!(universities.len(), PAYLOADS.len());
+  for (payload, (university_id, department_id)) in PAYLOADS.iter().zip(ids) {
+    assert_eq!(universities[&university_id], *payload);
+    let departments = store.get_departments(university_id).await.unwrap();
+    assert_eq!(departments, HashMap::from([(department_id, payload.to_string())]));
+    let subscriptions = store.get_subscriptions(payload).await.unwrap();
+    assert_eq!(subscriptions, HashMap::from([(university_id, vec![department_id])]), "{:?}", payload);
+  }
+}
+
+#[tokio::test]
+async fn memory_store_keeps_payloads_literally() {
+  store_keeps_payloads_literally(&MemoryStore::new()).await;
+}
+
+#[tokio::test]
+async fn sqlite_store_keeps_payloads_literally() {
+  let db = TempDb::new();
+  store_keeps_payloads_literally(open_store(&db.url(), 1).await.unwrap().as_ref()).await;
+}
@@ -0,0 +1,178 @@
+//! Removal of universities and departments leaves nothing referring to them.
+//!
+//! Checked against memory and SQLite stores, and against a real MySQL server when run with
+//! `--ignored` and PROSPECT_TEST_MYSQL set to `user:passwd@addr` of a server the tests may
+//! create the Prospect database on.
+
+use chrono::Utc;
+use rand::Rng;
+
+use prospect_backend::database::{open_store, MemoryStore, ProspectSqlPool, ProspectStore};
+use prospect_backend::wechat::types::*;
+
+mod common;
+use common::TempDb;
+
+async fn mysql() -> ProspectSqlPool {
+  let url = std::env::var("PROSPECT_TEST_MYSQL").expect("PROSPECT_TEST_MYSQL not set");
+  ProspectSqlPool::init(&format!("mysql://{}", url)).await.unwrap();
+  ProspectSqlPool::connect(&format!("mysql://{}", url), 2).await.unwrap()
+}
+
+/// unique name so that tests sharing a server do not collide
+fn unique(prefix: &str) -> String {
+  format!("{}_{:016x}", prefix, rand::thread_rng().gen::<u64>())
+}
+
+/// university with two departments, each subscribed by two users and with a post,
+/// notifications of first department are delivered, those of second are not.
+struct Populated {
+  university_id: u32,
+  first: u32,
+  second: u32,
+  users: Vec<String>,
+  asset_path: String,
+}
+
+async fn populate(store: &dyn ProspectStore) -> Populated {
+  let university_id = store.add_university(&unique("uni"), "university").await.unwrap();
+  let first = store.add_department(university_id, &unique("dep"), "first").await.unwrap();
+  let second = store.add_department(university_id, &unique("dep"), "second").await.unwrap();
+  let mut users = Vec::new();
+  for _ in 0..2 {
+    let open_id = unique("user");
+    store.subscribe_user(SubscribeInfo {
+      open_id: open_id.clone(),
+      access_token: "".into(),
+      info: [first, second]
+        .into_iter()
+        .map(|department_id| SubscribeDetail { school_code: university_id, department_code: department_id, oper: 0 })
+        .collect(),
+    }).await.unwrap();
+    let messages = store.outbox_enqueue(vec![
+      OutboxMessage::new(open_id.clone(), university_id, first, PAPER_UPDATE_TEMPLATE.into(), "{}".into()),
+      OutboxMessage::new(open_id.clone(), university_id, second, PAPER_UPDATE_TEMPLATE.into(), "{}".into()),
+    ]).await.unwrap();
+    store.outbox_mark_delivered(messages[0].id).await.unwrap();
+    users.push(open_id);
+  }
+  let asset_path = unique("post");
+  for department_id in [first, second] {
+    store.add_post(&PostRecord {
+      title: "post".into(),
+      img_source_link: "".into(),
+      asset_path: format!("{}/{}.md", asset_path, department_id),
+      author: "author".into(),
+      publish_date: Utc::now(),
+      status: PostStatus::Published,
+      university_id: Some(university_id),
+      department_id: Some(department_id),
+    }).await.unwrap();
+  }
+  Populated { university_id, first, second, users, asset_path }
+}
+
+/// departments, subscriptions, undelivered and delivered notifications, and posts
+/// still referring to university, or to one of its departments if given.
+async fn leftovers(store: &dyn ProspectStore, p: &Populated, department_id: Option<u32>) -> [usize; 5] {
+  let matches = |d: u32| department_id.is_none_or(|id| id == d);
+  let departments = store.get_departments(p.university_id).await.unwrap_or_default();
+  let mut counts = [departments.keys().filter(|d| matches(**d)).count(), 0, 0, 0, 0];
+  for open_id in &p.users {
+    let subscriptions = store.get_subscriptions(open_id).await.unwrap();
+    counts[1] += subscriptions.get(&p.university_id).map_or(0, |ds| ds.iter().filter(|d| matches(**d)).count());
+    for m in store.outbox_history(open_id).await.unwrap() {
+      if m.university_id == p.university_id && matches(m.department_id) {
+        counts[if m.delivered_at.is_none() { 2 } else { 3 }] += 1;
+      }
+    }
+  }
+  let info = WaterFallInfo {
+    university_id: Some(p.university_id),
+    department_id,
+    page_size: Some(WaterFallInfo::MAX_PAGE_SIZE),
+    ..Default::default()
+  };
+  counts[4] = store.get_posts(&info, None).await.unwrap().0.len();
+  counts
+}
+
+async fn remove_department_leaves_no_orphans(store: &dyn ProspectStore) {
+  let p = populate(store).await;
+
+  assert_eq!(store.remove_department(p.university_id, p.first).await.unwrap(), 2);
+  // delivered notifications are kept as history
+  assert_eq!(leftovers(store, &p, Some(p.first)).await, [0, 0, 0, 2, 0]);
+  // the other department is untouched
+  assert_eq!(leftovers(store, &p, Some(p.second)).await, [1, 2, 2, 0, 1]);
+  // post of removed department is kept, untagged
+  let (posts, _) = store.get_posts(&WaterFallInfo { page_size: Some(WaterFallInfo::MAX_PAGE_SIZE), ..Default::default() }, None).await.unwrap();
+  assert!(posts.iter().any(|post| post.post_id == format!("{}/{}.md", p.asset_path, p.first)));
+
+  assert!(matches!(store.remove_department(p.university_id, p.first).await, Err(sqlx::Error::RowNotFound)));
+  let r = store.subscribe_user(SubscribeInfo {
+    open_id: unique("user"),
+    access_token: "".into(),
+    info: vec![SubscribeDetail { school_code: p.university_id, department_code: p.first, oper: 0 }],
+  }).await;
+  assert!(matches!(r, Err(sqlx::Error::RowNotFound)));
+
+  store.remove_university(p.university_id).await.unwrap();
+}
+
+async fn remove_university_leaves_no_orphans(store: &dyn ProspectStore) {
+  let p = populate(store).await;
+
+  assert_eq!(store.remove_university(p.university_id).await.unwrap(), 4);
+  assert_eq!(leftovers(store, &p, None).await, [0, 0, 0, 2, 0]);
+  assert!(!store.get_universities().await.unwrap().contains_key(&p.university_id));
+
+  assert!(matches!(store.remove_university(p.university_id).await, Err(sqlx::Error::RowNotFound)));
+}
+
+#[tokio::test]
+async fn memory_remove_department_leaves_no_orphans() {
+  remove_department_leaves_no_orphans(&MemoryStore::new()).await;
+}
+
+#[tokio::test]
+async fn memory_remove_university_leaves_no_orphans() {
+  remove_university_leaves_no_orphans(&MemoryStore::new()).await;
+}
+
+#[tokio::test]
+async fn sqlite_remove_department_leaves_no_orphans() {
+  let db = TempDb::new();
+  remove_department_leaves_no_orphans(open_store(&db.url(), 1).await.unwrap().as_ref()).await;
+}
+
+#[tokio::test]
+async fn sqlite_remove_university_leaves_no_orphans() {
+  let db = TempDb::new();
+  remove_university_leaves_no_orphans(open_store(&db.url(), 1).await.unwrap().as_ref()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a MySQL server given by PROSPECT_TEST_MYSQL"]
+async fn mysql_remove_department_leaves_no_orphans() {
+  remove_department_leaves_no_orphans(&mysql().await).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a MySQL server given by PROSPECT_TEST_MYSQL"]
+async fn mysql_remove_university_leaves_no_orphans() {
+  remove_university_leaves_no_orphans(&mysql().await).await;
+}
+
+#[tokio::test]
+async fn memory_outbox_ids_are_not_reused() {
+  let store = MemoryStore::new();
+  let p = populate(&store).await;
+  let last = store.outbox_history(&p.users[1]).await.unwrap().iter().map(|m| m.id).max().unwrap();
+  // drops the undelivered notifications, among them the one with highest id
+  store.remove_department(p.university_id, p.second).await.unwrap();
+  let messages = store.outbox_enqueue(vec![
+    OutboxMessage::new(p.users[0].clone(), p.university_id, p.first, PAPER_UPDATE_TEMPLATE.into(), "{}".into()),
+  ]).await.unwrap();
+  assert!(messages[0].id > last);
+}
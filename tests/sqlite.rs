@@ -0,0 +1,183 @@
+//! Login, subscribe and university listing against a SQLite file.
+
+use chrono::Duration;
+use sqlx::{Connection, SqliteConnection};
+
+use prospect_backend::database::{open_store, schema_version, LogInErr, ProspectSqlitePool, SignUpErr};
+use prospect_backend::types::{LogInInfo, SignUpInfo};
+use prospect_backend::wechat::handlers::*;
+use prospect_backend::wechat::mock::*;
+use prospect_backend::wechat::types::*;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn migrations_are_recorded() {
+  let db = TempDb::new();
+  assert_eq!(schema_version(&db.url()).await.unwrap(), (0, ProspectSqlitePool::latest_version()));
+  open_store(&db.url(), 1).await.unwrap();
+  // opening again finds nothing to apply
+  open_store(&db.url(), 1).await.unwrap();
+  let latest = ProspectSqlitePool::latest_version();
+  assert_eq!(schema_version(&db.url()).await.unwrap(), (latest, latest));
+}
+
+#[tokio::test]
+async fn column_migrations_are_safe_to_repeat() {
+  let db = TempDb::new();
+  open_store(&db.url(), 1).await.unwrap();
+  // as if the columns were changed but the versions were never recorded
+  let mut conn = SqliteConnection::connect(&db.url()).await.unwrap();
+  sqlx::query("DELETE FROM schemaVersion WHERE version >= 7")
+    .execute(&mut conn).await.unwrap();
+  open_store(&db.url(), 1).await.unwrap();
+  let latest = ProspectSqlitePool::latest_version();
+  assert_eq!(schema_version(&db.url()).await.unwrap(), (latest, latest));
+}
+
+#[tokio::test]
+async fn log_in_subscribe_and_list() {
+  let db = TempDb::new();
+  let server = MockWechatServer::start().await;
+  let options = options(&["--database-url", &db.url()]);
+  let store = open_store(&options.database_url(), 1).await.unwrap();
+  let ctx = context_with_store(&server, options, store);
+  let university_id = ctx.store.add_university("u", "university").await.unwrap();
+  let department_id = ctx.store.add_department(university_id, "d", "department").await.unwrap();
+  assert_eq!(ctx.store.add_department(university_id, "d", "department").await.unwrap(), department_id);
+
+  let info = CodeInfo { code: "abc".into(), open_id: "".into(), access_token: "".into(), device: "".into() };
+  let reply = body(send_code_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["err_code"], 0);
+  let open_id = reply["open_id"].as_str().unwrap().to_string();
+  let access_token = reply["access_token"].as_str().unwrap().to_string();
+
+  // only hash of token is kept
+  let mut conn = SqliteConnection::connect(&db.url()).await.unwrap();
+  let (stored, ): (String, ) = sqlx::query_as("SELECT token_hash FROM wechatSession WHERE open_id = ?")
+    .bind(&open_id)
+    .fetch_one(&mut conn).await.unwrap();
+  assert_eq!(stored, AccessToken::from(access_token.clone()).hash());
+  assert_ne!(stored, access_token);
+
+  let info = CodeInfo { code: "".into(), open_id: open_id.clone(), access_token, device: "".into() };
+  let reply = body(send_code_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["err_code"], 0);
+  let access_token = reply["access_token"].as_str().unwrap().to_string();
+
+  let info = SubscribeInfo {
+    open_id: open_id.clone(),
+    access_token: access_token.clone(),
+    info: vec![SubscribeDetail { school_code: university_id, department_code: department_id, oper: 0 }],
+  };
+  assert_eq!(post_as_user(&ctx, subscribe_handler, &[], &info).await["err_code"], 0);
+  let info = GetSubscribeInfo { open_id: open_id.clone(), access_token };
+  let reply = post_as_user(&ctx, get_user_subscribe_handler, &[], &info).await;
+  assert_eq!(reply["info"][university_id.to_string()][0], department_id);
+  assert_eq!(ctx.store.get_users(university_id, department_id).await.unwrap(), vec![open_id.clone()]);
+
+  let reply = body(get_university_handler(ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["universities"][university_id.to_string()], "university");
+  let info = GetDepartmentInfo { university_code: university_id };
+  let reply = body(get_department_handler(info, ctx.clone()).await.unwrap()).await;
+  assert_eq!(reply["departments"][department_id.to_string()], "department");
+
+  // foreign keys cascade and removal reports subscriptions
+  assert_eq!(ctx.store.remove_university(university_id).await.unwrap(), 1);
+  assert!(ctx.store.get_subscriptions(&open_id).await.unwrap().is_empty());
+  assert!(ctx.store.get_universities().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn waterfall_pages_posts_within_one_second() {
+  let db = TempDb::new();
+  let server = MockWechatServer::start().await;
+  let options = options(&["--database-url", &db.url()]);
+  let store = open_store(&options.database_url(), 1).await.unwrap();
+  page_through_same_second(&context_with_store(&server, options, store)).await;
+}
+
+#[tokio::test]
+async fn replaced_token_is_accepted_within_grace() {
+  let db = TempDb::new();
+  let store = open_store(&db.url(), 1).await.unwrap();
+  let now = AccessToken::new();
+  store.record_token("o", now.clone(), "phone").await.unwrap();
+  let rotation = TokenRotation { rotate_after: Duration::zero(), grace: Duration::seconds(30) };
+
+  let rotated = store.valid_token_and_update(now.clone(), "o", rotation).await.unwrap();
+  assert_ne!(rotated.token, now.token);
+  let kept = store.valid_token_and_update(now.clone(), "o", rotation).await.unwrap();
+  assert_eq!(kept.token, now.token);
+  assert!(kept.expired <= rotated.expired);
+  assert!(store.is_valid_access_token("o", now.clone()).await.unwrap());
+  assert!(store.is_valid_access_token("o", rotated.clone()).await.unwrap());
+
+  // rotating again drops the first token
+  let rotation = TokenRotation { grace: Duration::zero(), ..rotation };
+  store.valid_token_and_update(rotated, "o", rotation).await.unwrap();
+  assert!(!store.is_valid_access_token("o", now.clone()).await.unwrap());
+  assert!(store.valid_token_and_update(now, "o", rotation).await.is_err());
+}
+
+#[tokio::test]
+async fn sign_up_and_log_in() {
+  let db = TempDb::new();
+  let store = open_store(&db.url(), 1).await.unwrap();
+  let info = SignUpInfo { username: "alice".into(), password: "secret".into() };
+  store.sign_up(info).await.unwrap();
+  let info = SignUpInfo { username: "alice".into(), password: "other".into() };
+  assert!(matches!(store.sign_up(info).await, Err(SignUpErr::UserExist)));
+
+  let info = LogInInfo { username: "alice".into(), password: "secret".into() };
+  let (user_id, token) = store.log_in(info).await.unwrap();
+  assert_eq!(user_id, 1);
+  assert_eq!(store.web_session_user(&token.token).await.unwrap(), user_id);
+  // only hash of token is kept
+  let mut conn = SqliteConnection::connect(&db.url()).await.unwrap();
+  let (stored, ): (String, ) = sqlx::query_as("SELECT token_hash FROM webSession WHERE user_id = ?")
+    .bind(user_id)
+    .fetch_one(&mut conn).await.unwrap();
+  assert_eq!(stored, token.hash());
+  assert_ne!(stored, token.token);
+  store.log_out(&token.token).await.unwrap();
+  assert!(matches!(store.web_session_user(&token.token).await, Err(sqlx::Error::RowNotFound)));
+  let info = LogInInfo { username: "alice".into(), password: "wrong".into() };
+  assert!(matches!(store.log_in(info).await, Err(LogInErr::InvalidCredentials)));
+  let info = LogInInfo { username: "bob".into(), password: "secret".into() };
+  assert!(matches!(store.log_in(info).await, Err(LogInErr::InvalidCredentials)));
+}
+
+#[tokio::test]
+async fn legacy_password_hash_is_upgraded() {
+  use crypto::digest::Digest;
+
+  let db = TempDb::new();
+  let store = open_store(&db.url(), 1).await.unwrap();
+  // account signed up before argon2, hex sha3-256 over password || salt
+  let salt = [1u8, 2, 3, 4, 5, 6, 7, 8];
+  let mut hasher = crypto::sha3::Sha3::sha3_256();
+  hasher.input(&[b"secret".as_slice(), salt.as_slice()].concat());
+  let mut conn = SqliteConnection::connect(&db.url()).await.unwrap();
+  sqlx::query("INSERT INTO UserAuth (username, salt, hash) VALUES (?, ?, ?)")
+    .bind("alice")
+    .bind(salt.as_slice())
+    .bind(hasher.result_str())
+    .execute(&mut conn).await.unwrap();
+  let hash = || sqlx::query_as::<_, (String, )>("SELECT hash FROM UserAuth WHERE username = 'alice'");
+
+  let info = LogInInfo { username: "alice".into(), password: "wrong".into() };
+  assert!(matches!(store.log_in(info).await, Err(LogInErr::InvalidCredentials)));
+  assert!(!hash().fetch_one(&mut conn).await.unwrap().0.starts_with("$argon2id$"));
+
+  let info = LogInInfo { username: "alice".into(), password: "secret".into() };
+  store.log_in(info).await.unwrap();
+  assert!(hash().fetch_one(&mut conn).await.unwrap().0.starts_with("$argon2id$"));
+
+  // upgraded hash still accepts the password
+  let info = LogInInfo { username: "alice".into(), password: "secret".into() };
+  store.log_in(info).await.unwrap();
+  let info = LogInInfo { username: "alice".into(), password: "wrong".into() };
+  assert!(matches!(store.log_in(info).await, Err(LogInErr::InvalidCredentials)));
+}
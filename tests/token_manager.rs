@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use chrono::Duration;
+use serde_json::json;
+
+use prospect_backend::wechat::api::WechatApi;
+use prospect_backend::wechat::mock::*;
+use prospect_backend::wechat::token::TokenManager;
+use prospect_backend::wechat::types::Error;
+
+#[tokio::test]
+async fn token_is_cached() {
+  let server = MockWechatServer::start().await;
+  let api = server.api();
+  let manager = TokenManager::new(Duration::seconds(300));
+  assert!(manager.remaining_lifetime().is_none());
+
+  assert_eq!(manager.get(&api).await.unwrap(), MOCK_ACCESS_TOKEN);
+  assert_eq!(manager.get(&api).await.unwrap(), MOCK_ACCESS_TOKEN);
+  assert_eq!(server.requests(MockEndpoint::GetAccessToken).len(), 1);
+
+  let remaining = manager.remaining_lifetime().unwrap();
+  assert!(remaining > Duration::seconds(7100) && remaining <= Duration::seconds(7200));
+}
+
+#[tokio::test]
+async fn concurrent_callers_share_one_refresh() {
+  let server = MockWechatServer::start().await;
+  let api: Arc<dyn WechatApi> = Arc::new(server.api());
+  let manager = Arc::new(TokenManager::new(Duration::seconds(300)));
+  let tasks = (0..20)
+    .map(|_| {
+      let manager = manager.clone();
+      let api = api.clone();
+      tokio::spawn(async move { manager.get(api.as_ref()).await })
+    })
+    .collect::<Vec<_>>();
+  for task in tasks {
+    assert_eq!(task.await.unwrap().unwrap(), MOCK_ACCESS_TOKEN);
+  }
+  assert_eq!(server.requests(MockEndpoint::GetAccessToken).len(), 1);
+}
+
+#[tokio::test]
+async fn token_refreshed_ahead_of_expiry() {
+  let server = MockWechatServer::start().await;
+  let api = server.api();
+  let manager = TokenManager::new(Duration::seconds(300));
+  server.push(MockEndpoint::GetAccessToken, MockReply::Json(json!({ "access_token": "short", "expires_in": 200 })));
+  assert_eq!(manager.get(&api).await.unwrap(), "short");
+  // less than refresh_ahead left, next call refreshes
+  assert_eq!(manager.get(&api).await.unwrap(), MOCK_ACCESS_TOKEN);
+  assert_eq!(server.requests(MockEndpoint::GetAccessToken).len(), 2);
+}
+
+#[tokio::test]
+async fn failed_refresh_keeps_valid_token() {
+  let server = MockWechatServer::start().await;
+  let api = server.api();
+  let manager = TokenManager::new(Duration::seconds(300));
+  server.push(MockEndpoint::GetAccessToken, MockReply::Json(json!({ "access_token": "short", "expires_in": 200 })));
+  server.push(MockEndpoint::GetAccessToken, MockReply::err(-1, "system busy"));
+  assert_eq!(manager.get(&api).await.unwrap(), "short");
+  assert_eq!(manager.get(&api).await.unwrap(), "short");
+}
+
+#[tokio::test]
+async fn errcode_is_surfaced() {
+  let server = MockWechatServer::start().await;
+  let manager = TokenManager::new(Duration::seconds(300));
+  server.push(MockEndpoint::GetAccessToken, MockReply::err(40013, "invalid appid"));
+  assert!(matches!(manager.get(&server.api()).await, Err(Error::InvalidAppId)));
+  assert!(manager.remaining_lifetime().is_none());
+}
+
+#[tokio::test]
+async fn missing_expires_in_is_an_error() {
+  let server = MockWechatServer::start().await;
+  let manager = TokenManager::new(Duration::seconds(300));
+  server.push(MockEndpoint::GetAccessToken, MockReply::Json(json!({ "access_token": "t" })));
+  assert!(matches!(manager.get(&server.api()).await, Err(Error::InvalidJsonFromWechat)));
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::json;
+
+use prospect_backend::wechat::api::WechatApi;
+use prospect_backend::wechat::mock::*;
+use prospect_backend::wechat::types::{Error, TemplateConfig, PAPER_UPDATE_TEMPLATE};
+
+#[tokio::test]
+async fn code2session_returns_open_id() {
+  let server = MockWechatServer::start().await;
+  let r = server.api().code2session("abc").await.unwrap();
+  assert_eq!(r.openid.as_deref(), Some("mock_open_id_abc"));
+
+  let requests = server.requests(MockEndpoint::Code2Session);
+  assert_eq!(requests.len(), 1);
+  assert_eq!(requests[0].query["appid"], MOCK_APP_ID);
+  assert_eq!(requests[0].query["secret"], MOCK_APP_SECRET);
+  assert_eq!(requests[0].query["js_code"], "abc");
+}
+
+#[tokio::test]
+async fn code2session_maps_errcode() {
+  let server = MockWechatServer::start().await;
+  server.push(MockEndpoint::Code2Session, MockReply::err(40029, "invalid code"));
+  let r = server.api().code2session("abc").await;
+  assert!(matches!(r, Err(Error::InvalidCode)));
+}
+
+#[tokio::test]
+async fn code2session_escapes_code() {
+  let server = MockWechatServer::start().await;
+  server.api().code2session("a&secret=evil").await.unwrap();
+  let requests = server.requests(MockEndpoint::Code2Session);
+  assert_eq!(requests[0].query["js_code"], "a&secret=evil");
+  assert_eq!(requests[0].query["secret"], MOCK_APP_SECRET);
+}
+
+#[tokio::test]
+async fn get_access_token_scripted() {
+  let server = MockWechatServer::start().await;
+  server.push(MockEndpoint::GetAccessToken, MockReply::Json(json!({ "access_token": "t1", "expires_in": 60 })));
+  let api = server.api();
+  let r = api.get_access_token().await.unwrap();
+  assert_eq!(r.access_token.as_deref(), Some("t1"));
+  assert_eq!(r.expires_in, Some(60));
+  // falls back to default once scripted replies are used up
+  let r = api.get_access_token().await.unwrap();
+  assert_eq!(r.access_token.as_deref(), Some(MOCK_ACCESS_TOKEN));
+}
+
+#[tokio::test]
+async fn get_access_token_http_error() {
+  let server = MockWechatServer::start().await;
+  server.push(MockEndpoint::GetAccessToken, MockReply::Status(502));
+  let r = server.api().get_access_token().await;
+  assert!(matches!(r, Err(Error::NetworkToWechatErr)));
+}
+
+#[tokio::test]
+async fn send_subscribe_message() {
+  let server = MockWechatServer::start().await;
+  let template = TemplateConfig::default().get(PAPER_UPDATE_TEMPLATE).unwrap().clone();
+  let vars = HashMap::from([
+    ("university", "u".to_string()),
+    ("department", "d".to_string()),
+    ("time", "2022-01-01 00:00:00".to_string()),
+  ]);
+  let message = template.render("user", &vars);
+  server.api().send_subscribe_message("token", &message).await.unwrap();
+
+  let requests = server.requests(MockEndpoint::SendSubscribeMessage);
+  assert_eq!(requests.len(), 1);
+  assert_eq!(requests[0].query["access_token"], "token");
+  let body = requests[0].body.as_ref().unwrap();
+  assert_eq!(body["touser"], "user");
+  assert_eq!(body["template_id"], template.template_id);
+  assert_eq!(body["miniprogram_state"], "developer");
+  assert_eq!(body["data"]["thing1"]["value"], "u");
+  assert_eq!(body["data"]["thing2"]["value"], "d");
+}
+
+#[test]
+fn render_substitutes_in_one_pass() {
+  let mut template = TemplateConfig::default().get(PAPER_UPDATE_TEMPLATE).unwrap().clone();
+  template.fields = BTreeMap::from([("thing1".to_string(), "{university}/{department} {unknown} {time".to_string())]);
+  let vars = HashMap::from([
+    ("university", "{department}".to_string()),
+    ("department", "{university}".to_string()),
+  ]);
+  let message = template.render("user", &vars);
+  assert_eq!(message.data["thing1"].value, "{department}/{university} {unknown} {time");
+}
+
+#[tokio::test]
+async fn send_subscribe_message_maps_errcode() {
+  let server = MockWechatServer::start().await;
+  server.push(MockEndpoint::SendSubscribeMessage, MockReply::err(43101, "user refuse to accept the msg"));
+  let template = TemplateConfig::default().get(PAPER_UPDATE_TEMPLATE).unwrap().clone();
+  let message = template.render("user", &HashMap::new());
+  let r = server.api().send_subscribe_message("token", &message).await;
+  assert!(matches!(r, Err(Error::NeedSubscribe)));
+  assert_eq!(message.data.keys().map(String::as_str).collect::<Vec<_>>(), ["thing1", "thing2", "time3"]);
+}